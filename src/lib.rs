@@ -25,10 +25,12 @@ use wasm_bindgen::prelude::*;
 pub mod rt;
 pub use self::rt::Pod;
 
+#[allow(dead_code)]
 struct Database {
 
 }
 
+#[allow(dead_code)]
 impl Database {
   fn new() -> Self {
     Database {
@@ -47,6 +49,7 @@ lazy_static! {
 }
 
 #[wasm_bindgen]
-pub fn exec(uid: i64, src: &str) -> String {
+#[allow(clippy::needless_return)]
+pub fn exec(_uid: i64, src: &str) -> String {
   return src.to_string();
 }
@@ -15,26 +15,298 @@
 // License along with this program.  If not, see
 // <https://www.gnu.org/licenses/.
 
+// This module spells out returns, field names and reference patterns.
+#![allow(clippy::needless_return)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::redundant_static_lifetimes)]
+#![allow(clippy::match_like_matches_macro)]
+#![allow(clippy::match_ref_pats)]
+#![allow(clippy::needless_borrowed_reference)]
+
 /// An error that might occur during computation.
-#[derive(Debug, Copy, Clone)]
+///
+/// Hosts can use `is_fatal` to decide whether a pod is still safe to
+/// use after an error, and `is_user_error` to decide whether the error
+/// should be reported as a problem with the program being evaluated.
+/// Errors that are neither come from the host's environment or from a
+/// request the engine can't serve, and leave the pod usable.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Error {
+  /// A time quota ran out. A user error.
   Time,
+  /// A space quota ran out. A user error.
   Space,
+  /// An object was not of the expected kind. Fatal.
   Tag,
+  /// A feature isn't implemented. No opcode returns this: `g` and `h`
+  /// are inert by design, and are left in the output like any other
+  /// stuck code. Neither fatal nor a user error.
   Stub,
+  /// The engine reached a state it should never reach. Fatal.
   Bug,
+  /// A pointer referred to a deleted object. Fatal.
   Null,
+  /// An internal assertion failed. Fatal.
   Assert,
+  /// Source text could not be parsed. A user error.
   Syntax,
-  Underflow,
+  /// An opcode that needed `needed` blocks found only `found` on the
+  /// stack. A user error.
+  Underflow { needed: usize, found: usize },
+  /// The `SUNDIAL_HOME` directory could not be read. Neither fatal nor
+  /// a user error.
   Home,
+  /// A term does not use its values exactly once. A user error.
+  Linear,
+  /// A block's stack effect doesn't match its annotation; `position`
+  /// is where the block would have appeared in the output. A user
+  /// error.
+  Arity { position: usize },
+  /// A replayed reduction did something other than what its trace
+  /// recorded, first at the given step. Neither fatal nor a user
+  /// error.
+  Replay { step: usize },
+  /// A thread met an opcode that its config doesn't allow. A user
+  /// error.
+  Forbidden,
+  /// Input could not be read, or output could not be written. Neither
+  /// fatal nor a user error.
+  Io,
+  /// A definition didn't reach a normal form within the probe quota.
+  /// A user error.
+  Diverge,
+  /// A definition's name would be read as opcodes rather than as a
+  /// word, so the definition could never be used. A user error.
+  Shadow,
+  /// A thread expanded more words than its config's expansion quota
+  /// allows. A user error.
+  Expansion,
+}
+
+impl Error {
+  /// Whether the error means the engine's state can't be trusted, so
+  /// the pod that raised it should be discarded.
+  pub fn is_fatal(&self) -> bool {
+    match self {
+      Error::Tag | Error::Bug | Error::Null | Error::Assert => {
+        return true;
+      }
+      Error::Time | Error::Space | Error::Stub | Error::Syntax |
+      Error::Underflow { .. } | Error::Home | Error::Linear |
+      Error::Arity { .. } | Error::Replay { .. } | Error::Forbidden |
+      Error::Io | Error::Diverge | Error::Shadow | Error::Expansion => {
+        return false;
+      }
+    }
+  }
+
+  /// Whether the error was caused by the program being evaluated,
+  /// rather than by the engine or its environment.
+  pub fn is_user_error(&self) -> bool {
+    match self {
+      Error::Time | Error::Space | Error::Syntax |
+      Error::Underflow { .. } | Error::Linear | Error::Arity { .. } |
+      Error::Forbidden | Error::Diverge | Error::Shadow |
+      Error::Expansion => {
+        return true;
+      }
+      Error::Tag | Error::Stub | Error::Bug | Error::Null |
+      Error::Assert | Error::Home | Error::Replay { .. } | Error::Io => {
+        return false;
+      }
+    }
+  }
 }
 
 /// The result of a computation.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// A Sundial opcode.
+/// Limits and modes that govern a reduction.
 #[derive(Debug, Copy, Clone)]
+pub struct Config {
+  /// The number of sequence nodes a thread may unfold while looking
+  /// for its next instruction.
+  pub continuation_quota: usize,
+  /// The number of blocks that may be nested in one another: brackets
+  /// open at once while parsing, or blocks built by `b` and `j` while
+  /// reducing. Going over fails with `Error::Space`.
+  pub nesting_quota: usize,
+  /// Whether an opcode without enough blocks on the stack fails with
+  /// `Error::Underflow`, rather than being left in the output.
+  pub strict_arity: bool,
+  pub strategy: Strategy,
+  /// Whether each block is checked against its stack-effect
+  /// annotation, if it has one, when it is pushed. See
+  /// `Heap::check_arity`.
+  pub check_arity: bool,
+  /// The opcodes a thread may run. Running any other fails with
+  /// `Error::Forbidden`.
+  pub allowed: OpcodeSet,
+  /// If set, each new definition is reduced again with its own name
+  /// bound, for at most this many steps. Definitions that don't reach
+  /// a normal form in time fail with `Error::Diverge`.
+  pub probe_quota: Option<u64>,
+  /// If set, the most nodes a single reduction may allocate, however
+  /// many it frees along the way. Going over fails with `Error::Space`.
+  pub alloc_quota: Option<u64>,
+  /// Whether a token made only of opcode letters, such as `ab`, parses
+  /// as those opcodes in turn rather than as a word. This hides any
+  /// word spelled that way, such as `cab` or `bead`.
+  pub opcode_runs: bool,
+  /// The number of reduced terms a pod remembers, so that evaluating
+  /// the same term again needn't reduce it. Terms are the same if they
+  /// have the same structure, however they are spaced. A remembered
+  /// term reports the steps it first took, and is only used when the
+  /// time quota would have allowed them. Zero turns the cache off.
+  pub cache_quota: usize,
+  /// Whether `Pod::to_string` lists definitions in the order they were
+  /// first made, rather than by name. A word that is redefined keeps
+  /// its place, so helpers stay ahead of the words that use them.
+  pub keep_order: bool,
+  /// The fuel each opcode uses up from the time quota. A step that
+  /// costs more than the fuel left still runs, so a reduction may use
+  /// a little more than its quota.
+  pub costs: CostModel,
+  /// If set, the first time a block is applied its body is reduced on
+  /// its own, for at most this many steps, and the result is reused
+  /// whenever the same block is applied again. The steps are charged
+  /// to the `a` that forced them. Bodies that don't reach a normal
+  /// form in time are run as they are.
+  pub call_by_need: Option<u64>,
+  /// Whether a thread that finds its own state broken panics, quoting
+  /// the code it was running, rather than failing with `Error::Bug`.
+  /// This is for debugging the engine, where a backtrace is worth more
+  /// than an error.
+  pub debug_panic_on_bug: bool,
+  /// If set, the most words a single reduction may expand, however
+  /// few steps the rest of its work takes. Going over fails with
+  /// `Error::Expansion`, which bounds recursion apart from the time
+  /// quota.
+  pub expansion_quota: Option<u64>,
+}
+
+/// A set of opcodes, named by their letters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OpcodeSet {
+  bits: u32,
+}
+
+impl OpcodeSet {
+  pub fn all() -> Self {
+    OpcodeSet {
+      bits: !0,
+    }
+  }
+
+  pub fn none() -> Self {
+    OpcodeSet {
+      bits: 0,
+    }
+  }
+
+  pub fn with(self, name: char) -> Self {
+    OpcodeSet {
+      bits: self.bits | OpcodeSet::get_bit(name),
+    }
+  }
+
+  pub fn without(self, name: char) -> Self {
+    OpcodeSet {
+      bits: self.bits & !OpcodeSet::get_bit(name),
+    }
+  }
+
+  pub fn contains(&self, name: char) -> bool {
+    let bit = OpcodeSet::get_bit(name);
+    return bit != 0 && self.bits & bit == bit;
+  }
+
+  fn get_bit(name: char) -> u32 {
+    if name.is_ascii_lowercase() {
+      return 1 << (name as u32 - 'a' as u32);
+    }
+    return 0;
+  }
+}
+
+/// The fuel each opcode costs to run, named by their letters. Every
+/// other step, such as pushing a block or expanding a word, costs one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CostModel {
+  costs: [u64; 26],
+}
+
+impl CostModel {
+  /// Every opcode costs one, so fuel counts steps.
+  pub fn uniform() -> Self {
+    CostModel {
+      costs: [1; 26],
+    }
+  }
+
+  /// Sets the cost of an opcode. Costs below one are raised to one, so
+  /// that every reduction ends within its quota.
+  pub fn with(self, name: char, cost: u64) -> Self {
+    let mut costs = self.costs;
+    if name.is_ascii_lowercase() {
+      costs[(name as u32 - 'a' as u32) as usize] = cost.max(1);
+    }
+    CostModel {
+      costs: costs,
+    }
+  }
+
+  pub fn get(&self, name: char) -> u64 {
+    if name.is_ascii_lowercase() {
+      return self.costs[(name as u32 - 'a' as u32) as usize];
+    }
+    return 1;
+  }
+}
+
+impl Default for CostModel {
+  fn default() -> Self {
+    return CostModel::uniform();
+  }
+}
+
+/// The order in which a thread reduces a term.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Strategy {
+  /// Reduce from left to right, leaving the bodies of blocks alone
+  /// until they are applied.
+  Outermost,
+  /// Reduce from left to right, but reduce the body of each block
+  /// before pushing it. Where the outermost strategy reaches a normal
+  /// form and this one terminates, the two agree up to the bodies of
+  /// blocks, which here are already reduced.
+  Innermost,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      continuation_quota: 1 << 16,
+      nesting_quota: 1 << 10,
+      strict_arity: false,
+      strategy: Strategy::Outermost,
+      check_arity: false,
+      allowed: OpcodeSet::all(),
+      probe_quota: None,
+      alloc_quota: None,
+      opcode_runs: false,
+      cache_quota: 0,
+      keep_order: false,
+      costs: CostModel::uniform(),
+      call_by_need: None,
+      debug_panic_on_bug: false,
+      expansion_quota: None,
+    }
+  }
+}
+
+/// A Sundial opcode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Opcode {
   App,
   Box,
@@ -44,9 +316,97 @@ enum Opcode {
   Swap,
   Prop,
   Forall,
+  Keep,
+  Append,
+  Rot,
+  Isolate,
+}
+
+impl Opcode {
+  /// The opcode that a letter stands for in source text, if any.
+  fn from_name(name: char) -> Option<Opcode> {
+    match name {
+      'a' => Some(Opcode::App),
+      'b' => Some(Opcode::Box),
+      'c' => Some(Opcode::Cat),
+      'd' => Some(Opcode::Copy),
+      'e' => Some(Opcode::Drop),
+      'f' => Some(Opcode::Swap),
+      'g' => Some(Opcode::Forall),
+      'h' => Some(Opcode::Prop),
+      'i' => Some(Opcode::Keep),
+      'j' => Some(Opcode::Append),
+      'k' => Some(Opcode::Rot),
+      'l' => Some(Opcode::Isolate),
+      _ => None,
+    }
+  }
+
+  /// The letter that stands for this opcode in source text.
+  fn get_name(&self) -> char {
+    match self {
+      Opcode::App => {
+        return 'a';
+      }
+      Opcode::Box => {
+        return 'b';
+      }
+      Opcode::Cat => {
+        return 'c';
+      }
+      Opcode::Copy => {
+        return 'd';
+      }
+      Opcode::Drop => {
+        return 'e';
+      }
+      Opcode::Swap => {
+        return 'f';
+      }
+      Opcode::Forall => {
+        return 'g';
+      }
+      Opcode::Prop => {
+        return 'h';
+      }
+      Opcode::Keep => {
+        return 'i';
+      }
+      Opcode::Append => {
+        return 'j';
+      }
+      Opcode::Rot => {
+        return 'k';
+      }
+      Opcode::Isolate => {
+        return 'l';
+      }
+    }
+  }
+
+  /// The number of blocks this opcode consumes, or `None` if it never
+  /// reduces.
+  fn get_arity(&self) -> Option<usize> {
+    match self {
+      Opcode::App | Opcode::Box | Opcode::Copy | Opcode::Drop |
+      Opcode::Isolate => {
+        return Some(1);
+      }
+      Opcode::Cat | Opcode::Swap | Opcode::Keep | Opcode::Append => {
+        return Some(2);
+      }
+      Opcode::Rot => {
+        return Some(3);
+      }
+      Opcode::Prop | Opcode::Forall => {
+        return None;
+      }
+    }
+  }
 }
 
 /// Halt the computation if the given condition is false.
+#[allow(dead_code)]
 fn assert(flag: Result<bool>) -> Result<()> {
   match flag {
     Ok(true) => {
@@ -68,19 +428,40 @@ lazy_static! {
     regex::Regex::new(WORD_PATTERN).unwrap()
   };
   static ref POD_INSERT_REGEX: regex::Regex = {
-    let src = format!(r"^:({})\s+(.*)", WORD_PATTERN);
+    let src = format!(r#"^:({})\s+(?:"([^"]*)"\s*)?(.*)"#, WORD_PATTERN);
+    regex::Regex::new(&src).unwrap()
+  };
+  static ref NAME_REGEX: regex::Regex = {
+    let src = format!(r"^{}$", WORD_PATTERN);
     regex::Regex::new(&src).unwrap()
   };
   static ref POD_DELETE_REGEX: regex::Regex = {
     let src = format!(r"^~({})\s*", WORD_PATTERN);
     regex::Regex::new(&src).unwrap()
   };
+  static ref ARITY_REGEX: regex::Regex = {
+    regex::Regex::new(r"^([0-9]+)-([0-9]+)$").unwrap()
+  };
   static ref HINT_REGEX: regex::Regex = {
     let src = format!(r"^\(({})\)$", WORD_PATTERN);
     regex::Regex::new(&src).unwrap()
   };
 }
 
+/// How much of a heap is reachable, as found by `Heap::audit`.
+#[cfg(test)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct AuditReport {
+  reachable: usize,
+  live: usize,
+  /// Nodes that are live but not reachable, which after a sweep means
+  /// marking missed something.
+  leaked: usize,
+}
+
+/// The default pod's source, for when `SUNDIAL_HOME` isn't set.
+const PRELUDE: &'static str = include_str!("../../lib/default.md");
+
 /// A pointer to some object.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 struct Gc {
@@ -88,11 +469,19 @@ struct Gc {
   generation: u64,
 }
 
+/// A point in a monotonic heap's history that it can be rolled back
+/// to. See `Heap::checkpoint`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Checkpoint {
+  frontier: usize,
+}
+
 use std::rc::Rc;
 use std::collections::HashMap;
 
 type Library = HashMap<Rc<str>, Gc>;
 
+#[derive(Clone)]
 enum Object {
   Id,
   Opcode(Opcode),
@@ -102,16 +491,47 @@ enum Object {
   Sequence(Gc, Gc),
 }
 
+/// The number of live nodes of each kind in a heap.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct KindCounts {
+  pub id: usize,
+  pub opcode: usize,
+  pub word: usize,
+  pub hint: usize,
+  pub block: usize,
+  pub sequence: usize,
+}
+
+#[derive(Clone)]
 struct Node {
   object: Object,
   generation: u64,
+  /// The order the node was allocated in. Nodes never change once
+  /// they are allocated, so a node's children always have lower
+  /// serials than it does.
+  serial: u64,
   is_visible: bool,
+  /// The number of blocks nested in one another in the node's term.
+  depth: usize,
 }
 
 /// A garbage-collected heap.
+#[derive(Clone)]
 struct Heap {
   nodes: Vec<Option<Node>>,
   generation: u64,
+  live: usize,
+  peak_live: usize,
+  allocated: u64,
+  is_monotonic: bool,
+  frontier: usize,
+  roots: Vec<Gc>,
+  pins: Vec<Option<Gc>>,
+  /// The value of `allocated` past which `put` fails.
+  alloc_limit: u64,
+  /// Whether the last allocation failed for want of free slots, which
+  /// collecting garbage may fix, rather than because of `alloc_limit`.
+  is_full: bool,
 }
 
 impl Gc {
@@ -168,11 +588,13 @@ impl Object {
 }
 
 impl Node {
-  fn new(object: Object, generation: u64) -> Self {
+  fn new(object: Object, generation: u64, serial: u64, depth: usize) -> Self {
     Node {
       object: object,
       generation: generation,
+      serial: serial,
       is_visible: false,
+      depth: depth,
     }
   }
 }
@@ -187,6 +609,15 @@ impl Heap {
     Heap {
       nodes: nodes,
       generation: 0,
+      live: 0,
+      peak_live: 0,
+      allocated: 0,
+      is_monotonic: false,
+      frontier: 0,
+      roots: Vec::new(),
+      pins: Vec::new(),
+      alloc_limit: u64::MAX,
+      is_full: false,
     }
   }
 
@@ -219,11 +650,22 @@ impl Heap {
     if self.is_id(fst)? {
       return Ok(snd);
     }
+    if self.is_id(snd)? {
+      return Ok(fst);
+    }
     if self.is_sequence(fst)? {
-      let fst_fst = self.get_sequence_fst(fst)?;
-      let fst_snd = self.get_sequence_snd(fst)?;
-      let rhs = self.new_sequence(fst_snd, snd)?;
-      return self.new_sequence(fst_fst, rhs);
+      let mut items = Vec::new();
+      let mut xs = fst;
+      while self.is_sequence(xs)? {
+        items.push(self.get_sequence_fst(xs)?);
+        xs = self.get_sequence_snd(xs)?;
+      }
+      items.push(xs);
+      let mut ys = snd;
+      for item in items.iter().rev() {
+        ys = self.new_sequence(*item, ys)?;
+      }
+      return Ok(ys);
     }
     let object = Object::Sequence(fst, snd);
     return self.put(object);
@@ -325,25 +767,62 @@ impl Heap {
     }
   }
 
+  /// Marks every node reachable from `root` as visible. A pointer to
+  /// a node allocated no earlier than the node holding it means the
+  /// heap is corrupt, and fails with `Error::Bug` rather than being
+  /// followed round a cycle forever.
   fn mark(&mut self, root: Gc) -> Result<()> {
-    match &mut self.nodes[root.index] {
-      &mut Some(ref mut node) => {
-        if node.generation != root.generation {
-          return Err(Error::Null);
-        }
-        node.is_visible = true;
-        match &node.object {
-          &Object::Block(body) => {
-            return self.mark(body);
+    let mut stack = vec![(root, u64::MAX)];
+    while let Some((pointer, bound)) = stack.pop() {
+      match self.nodes.get_mut(pointer.index) {
+        Some(&mut Some(ref mut node)) => {
+          if node.generation != pointer.generation {
+            return Err(Error::Null);
           }
-          &Object::Sequence(fst, snd) => {
-            self.mark(fst)?;
-            return self.mark(snd);
+          if node.serial >= bound {
+            return Err(Error::Bug);
           }
-          _ => {
-            return Ok(());
+          if node.is_visible {
+            continue;
+          }
+          node.is_visible = true;
+          match &node.object {
+            &Object::Block(body) => {
+              stack.push((body, node.serial));
+            }
+            &Object::Sequence(fst, snd) => {
+              stack.push((snd, node.serial));
+              stack.push((fst, node.serial));
+            }
+            _ => {
+              //
+            }
           }
         }
+        _ => {
+          return Err(Error::Null);
+        }
+      }
+    }
+    return Ok(());
+  }
+
+  /// Returns `child`, which `parent` points to, after checking that it
+  /// was allocated first. Every pointer in a well-formed heap points
+  /// to an older node, so a walk that follows pointers through here
+  /// fails with `Error::Bug` on a corrupt heap rather than going round
+  /// a cycle forever.
+  fn follow(&self, parent: Gc, child: Gc) -> Result<Gc> {
+    if self.get_serial(child)? >= self.get_serial(parent)? {
+      return Err(Error::Bug);
+    }
+    return Ok(child);
+  }
+
+  fn get_serial(&self, pointer: Gc) -> Result<u64> {
+    match self.nodes.get(pointer.index) {
+      Some(&Some(ref node)) if node.generation == pointer.generation => {
+        return Ok(node.serial);
       }
       _ => {
         return Err(Error::Null);
@@ -351,7 +830,169 @@ impl Heap {
     }
   }
 
-  fn sweep(&mut self) -> Result<()> {
+  fn count_by_kind(&self) -> KindCounts {
+    let mut counts = KindCounts::default();
+    for node in self.nodes.iter().flatten() {
+      match node.object {
+        Object::Id => {
+          counts.id += 1;
+        }
+        Object::Opcode(_) => {
+          counts.opcode += 1;
+        }
+        Object::Word(_) => {
+          counts.word += 1;
+        }
+        Object::Hint(_) => {
+          counts.hint += 1;
+        }
+        Object::Block(_) => {
+          counts.block += 1;
+        }
+        Object::Sequence(_, _) => {
+          counts.sequence += 1;
+        }
+      }
+    }
+    return counts;
+  }
+
+  /// Adds up how many times each word appears in a term, including in
+  /// the bodies of its blocks. Shared subterms count once for each
+  /// place they appear.
+  fn count_words(
+    &self, root: Gc, counts: &mut HashMap<Rc<str>, usize>) -> Result<()> {
+    let mut stack = vec![root];
+    while let Some(pointer) = stack.pop() {
+      match self.get_ref(pointer)? {
+        &Object::Word(ref value) => {
+          *counts.entry(value.clone()).or_insert(0) += 1;
+        }
+        &Object::Block(body) => {
+          stack.push(self.follow(pointer, body)?);
+        }
+        &Object::Sequence(fst, snd) => {
+          stack.push(self.follow(pointer, snd)?);
+          stack.push(self.follow(pointer, fst)?);
+        }
+        _ => {
+          continue;
+        }
+      }
+    }
+    return Ok(());
+  }
+
+  /// Finds every subterm of `root` structurally equal to `pattern`,
+  /// in the order they appear. The subterms of a term are its blocks,
+  /// their bodies, and its sequences and their elements.
+  fn find(&self, root: Gc, pattern: Gc) -> Result<Vec<Gc>> {
+    let mut found = Vec::new();
+    let mut stack = vec![root];
+    while let Some(pointer) = stack.pop() {
+      if self.diff(pointer, pattern)?.is_none() {
+        found.push(pointer);
+      }
+      match self.get_ref(pointer)? {
+        &Object::Block(body) => {
+          stack.push(self.follow(pointer, body)?);
+        }
+        &Object::Sequence(fst, snd) => {
+          stack.push(self.follow(pointer, snd)?);
+          stack.push(self.follow(pointer, fst)?);
+        }
+        _ => {
+          //
+        }
+      }
+    }
+    return Ok(found);
+  }
+
+  /// Draws the terms reachable from the given roots as a Graphviz
+  /// `digraph`. Each node appears once, so a shared subterm has an
+  /// edge from everything that refers to it.
+  fn to_dot(&self, roots: &[Gc]) -> Result<String> {
+    let mut target = String::from("digraph {\n");
+    let mut visited = std::collections::HashSet::new();
+    let mut stack: Vec<Gc> = roots.iter().rev().copied().collect();
+    while let Some(pointer) = stack.pop() {
+      if !visited.insert(pointer.index) {
+        continue;
+      }
+      let (label, children) = match self.get_ref(pointer)? {
+        &Object::Id => {
+          ("id".to_string(), vec![])
+        }
+        &Object::Opcode(_) => {
+          (self.to_source(pointer)?, vec![])
+        }
+        &Object::Word(ref value) => {
+          (value.to_string(), vec![])
+        }
+        &Object::Hint(ref value) => {
+          (format!("({})", value), vec![])
+        }
+        &Object::Block(body) => {
+          ("[]".to_string(), vec![body])
+        }
+        &Object::Sequence(fst, snd) => {
+          ("seq".to_string(), vec![fst, snd])
+        }
+      };
+      target.push_str(&format!("  n{} [label={:?}];\n", pointer.index, label));
+      for child in children.iter() {
+        target.push_str(
+          &format!("  n{} -> n{};\n", pointer.index, child.index));
+      }
+      for child in children.iter().rev() {
+        stack.push(*child);
+      }
+    }
+    target.push_str("}\n");
+    return Ok(target);
+  }
+
+  /// Keeps a pointer alive across collections until it is popped.
+  fn push_root(&mut self, root: Gc) {
+    self.roots.push(root);
+  }
+
+  fn pop_root(&mut self) -> Result<Gc> {
+    return self.roots.pop().ok_or(Error::Bug);
+  }
+
+  fn mark_roots(&mut self) -> Result<()> {
+    for index in 0..self.roots.len() {
+      let root = self.roots[index];
+      self.mark(root)?;
+    }
+    for index in 0..self.pins.len() {
+      if let Some(pin) = self.pins[index] {
+        self.mark(pin)?;
+      }
+    }
+    return Ok(());
+  }
+
+  /// Keeps a pointer alive across collections until it is unpinned,
+  /// returning the slot it was pinned in.
+  fn pin(&mut self, root: Gc) -> usize {
+    match self.pins.iter().position(|pin| pin.is_none()) {
+      Some(index) => {
+        self.pins[index] = Some(root);
+        return index;
+      }
+      None => {
+        self.pins.push(Some(root));
+        return self.pins.len() - 1;
+      }
+    }
+  }
+
+  /// Deletes every node not marked since the last sweep, returning
+  /// the number of nodes deleted.
+  fn sweep(&mut self) -> Result<usize> {
     let mut nodes_deleted = 0;
     for maybe_node in self.nodes.iter_mut() {
       let should_delete_node;
@@ -370,513 +1011,5707 @@ impl Heap {
         nodes_deleted += 1;
       }
     }
+    self.live -= nodes_deleted;
     self.generation += 1;
-    println!(
-      "[gc] deleted: {} generation: {}", nodes_deleted, self.generation);
-    return Ok(());
+    return Ok(nodes_deleted);
   }
 
-  fn put(&mut self, object: Object) -> Result<Gc> {
-    for (index, maybe_node) in self.nodes.iter_mut().enumerate() {
+  /// Makes allocation order a pure function of the program: every
+  /// node goes in the slot after the previous one, and slots freed by
+  /// a sweep are never reused.
+  fn set_monotonic(&mut self, is_monotonic: bool) {
+    self.is_monotonic = is_monotonic;
+    self.frontier = 0;
+    for (index, maybe_node) in self.nodes.iter().enumerate() {
       if maybe_node.is_some() {
-        continue;
+        self.frontier = index + 1;
       }
-      let node = Node::new(object, self.generation);
-      let pointer = Gc::new(index, self.generation);
-      *maybe_node = Some(node);
-      return Ok(pointer);
     }
-    return Err(Error::Space);
   }
 
-  fn get_ref(&self, pointer: Gc) -> Result<&Object> {
-    match &self.nodes[pointer.index] {
-      &Some(ref node) => {
-        if node.generation == pointer.generation {
-          return Ok(&node.object);
-        }
-        return Err(Error::Null);
-      }
-      None => {
-        return Err(Error::Null);
+  /// Marks the current end of a monotonic heap. Every slot past it is
+  /// empty, so anything found there later was allocated since.
+  fn checkpoint(&self) -> Checkpoint {
+    return Checkpoint {
+      frontier: self.frontier,
+    };
+  }
+
+  /// Deletes every node allocated since the checkpoint was taken,
+  /// returning the number deleted. Nodes swept in the meantime stay
+  /// deleted. The generation moves on, as it does after a sweep, so a
+  /// pointer to a rolled back node fails with `Error::Null` even once
+  /// its slot is filled again. Fails with `Error::Assert` unless the
+  /// heap is monotonic.
+  fn rollback(&mut self, checkpoint: Checkpoint) -> Result<usize> {
+    if !self.is_monotonic || checkpoint.frontier > self.frontier {
+      return Err(Error::Assert);
+    }
+    let mut nodes_deleted = 0;
+    for maybe_node in self.nodes.iter_mut().skip(checkpoint.frontier) {
+      if maybe_node.take().is_some() {
+        nodes_deleted += 1;
       }
     }
+    self.live -= nodes_deleted;
+    self.frontier = checkpoint.frontier;
+    self.generation += 1;
+    return Ok(nodes_deleted);
   }
-}
 
-fn parse(src: &str, heap: &mut Heap) -> Result<Gc> {
-  let mut build = Vec::new();
-  let mut stack = Vec::new();
-  let src = src.replace("[", "[ ");
-  let src = src.replace("]", " ]");
-  for word in src.split_whitespace() {
-    match word {
-      "[" => {
-        stack.push(build);
-        build = Vec::new();
-      }
-      "]" => {
-        let prev = stack.pop().ok_or(Error::Syntax)?;
-        let mut xs = heap.new_id()?;
-        for object in build.iter().rev() {
-          xs = heap.new_sequence(*object, xs)?;
-        }
-        xs = heap.new_block(xs)?;
-        build = prev;
-        build.push(xs);
-      }
-      "a" => {
-        let opcode = Opcode::App;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
-      }
-      "b" => {
-        let opcode = Opcode::Box;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
-      }
-      "c" => {
-        let opcode = Opcode::Cat;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
-      }
-      "d" => {
-        let opcode = Opcode::Copy;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
-      }
-      "e" => {
-        let opcode = Opcode::Drop;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
+  /// The number of blocks nested in one another in a term.
+  fn get_depth(&self, pointer: Gc) -> Result<usize> {
+    match self.nodes.get(pointer.index) {
+      Some(&Some(ref node)) if node.generation == pointer.generation => {
+        return Ok(node.depth);
       }
-      "f" => {
-        let opcode = Opcode::Swap;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
+      _ => {
+        return Err(Error::Null);
       }
-      "g" => {
-        let opcode = Opcode::Forall;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
+    }
+  }
+
+  /// The depth a node holding the given object would have.
+  fn get_object_depth(&self, object: &Object) -> usize {
+    match object {
+      &Object::Block(body) => {
+        return self.get_depth(body).unwrap_or(0) + 1;
       }
-      "h" => {
-        let opcode = Opcode::Prop;
-        let object = heap.new_opcode(opcode)?;
-        build.push(object);
+      &Object::Sequence(fst, snd) => {
+        let fst = self.get_depth(fst).unwrap_or(0);
+        let snd = self.get_depth(snd).unwrap_or(0);
+        return fst.max(snd);
       }
       _ => {
-        if word.len() == 1 {
-          if word.chars().all(|x| x.is_lowercase()) {
-            return Err(Error::Syntax);
-          }
-        }
-        if let Some(data) = HINT_REGEX.captures(&word) {
-          let name = data.get(1).ok_or(Error::Bug)?.as_str();
-          let object = heap.new_hint(name.into())?;
-          build.push(object);
-        } else {
-          let object = heap.new_word(word.into())?;
-          build.push(object);
-        }
+        return 0;
       }
     }
   }
-  if !stack.is_empty() {
-    return Err(Error::Syntax);
-  }
-  let mut xs = heap.new_id()?;
-  for object in build.iter().rev() {
-    xs = heap.new_sequence(*object, xs)?;
-  }
-  return Ok(xs);
-}
 
-fn quote(root: Gc, heap: &Heap, buf: &mut String) -> Result<()> {
-  match heap.get_ref(root)? {
-    &Object::Id => {
-      //
+  fn put(&mut self, object: Object) -> Result<Gc> {
+    self.is_full = false;
+    if self.allocated >= self.alloc_limit {
+      return Err(Error::Space);
     }
-    &Object::Opcode(ref value) => {
-      match value {
-        Opcode::App => {
-          buf.push('a');
-        }
-        Opcode::Box => {
-          buf.push('b');
-        }
-        Opcode::Cat => {
-          buf.push('c');
-        }
-        Opcode::Copy => {
-          buf.push('d');
-        }
-        Opcode::Drop => {
-          buf.push('e');
-        }
-        Opcode::Swap => {
-          buf.push('f');
-        }
-        Opcode::Forall => {
-          buf.push('g');
-        }
-        Opcode::Prop => {
-          buf.push('h');
-        }
+    let depth = self.get_object_depth(&object);
+    let start = if self.is_monotonic { self.frontier } else { 0 };
+    for (index, maybe_node) in self.nodes.iter_mut().enumerate().skip(start) {
+      if maybe_node.is_some() {
+        continue;
       }
-    }
-    &Object::Word(ref value) => {
-      buf.push_str(&value);
-    }
-    &Object::Hint(ref value) => {
-      buf.push('(');
-      buf.push_str(&value);
-      buf.push(')');
-    }
-    &Object::Block(body) => {
-      buf.push('[');
-      quote(body, heap, buf)?;
-      buf.push(']');
-    }
-    &Object::Sequence(fst, snd) => {
-      quote(fst, heap, buf)?;
-      if !heap.is_id(snd)? {
-        buf.push(' ');
-        quote(snd, heap, buf)?;
+      self.frontier = index + 1;
+      let node = Node::new(object, self.generation, self.allocated, depth);
+      let pointer = Gc::new(index, self.generation);
+      *maybe_node = Some(node);
+      self.live += 1;
+      self.allocated += 1;
+      if self.live > self.peak_live {
+        self.peak_live = self.live;
       }
+      return Ok(pointer);
     }
+    self.is_full = true;
+    return Err(Error::Space);
   }
-  return Ok(());
-}
 
-fn reduce(
-  continuation: Gc,
-  heap: &mut Heap,
-  tab: &Library,
-  mut time_quota: u64) -> Result<Gc> {
-  let mut thread = Thread::with_continuation(continuation);
-  while time_quota > 0 && thread.has_continuation() {
-    time_quota -= 1;
-    thread.step(heap, tab)?;
-  }
-  if thread.has_continuation() {
-    let snd = thread.get_continuation(heap)?;
-    let fst = thread.get_environment(heap)?;
-    return heap.new_sequence(fst, snd);
+  /// Allocates several objects in a single pass over the free slots,
+  /// returning their pointers in order. Either every object is
+  /// allocated or, if there isn't room for them all, none are.
+  fn put_many(&mut self, objects: Vec<Object>) -> Result<Vec<Gc>> {
+    let slots = self.find_free_slots(objects.len())?;
+    let depths = objects.iter()
+      .map(|object| self.get_object_depth(object))
+      .collect();
+    return Ok(self.fill_slots(slots, objects, depths));
   }
-  return thread.get_environment(heap);
-}
-
-use std::collections::VecDeque;
-
-#[derive(Debug, Clone)]
-struct Frame {
-  con: VecDeque<Gc>,
-  env: Vec<Gc>,
-  err: Vec<Gc>,
-}
 
-impl Frame {
-  fn new(root: Gc) -> Self {
-    let mut con = VecDeque::new();
-    con.push_back(root);
-    Frame {
-      con: con,
-      env: vec![],
-      err: vec![],
+  /// Allocates a sequence of `items`, in order, in a single pass over
+  /// the free slots. The items must be neither identities nor
+  /// sequences, so the result is what folding them together with
+  /// `new_sequence` would give.
+  fn new_list(&mut self, items: &[Gc]) -> Result<Gc> {
+    match items.len() {
+      0 => {
+        return self.new_id();
+      }
+      1 => {
+        return Ok(items[0]);
+      }
+      _ => {
+        //
+      }
+    }
+    let slots = self.find_free_slots(items.len() - 1)?;
+    let last = items[items.len() - 1];
+    let mut objects = Vec::with_capacity(slots.len());
+    for (index, item) in items[..slots.len()].iter().enumerate() {
+      let next = match slots.get(index + 1) {
+        Some(slot) => Gc::new(*slot, self.generation),
+        None => last,
+      };
+      objects.push(Object::Sequence(*item, next));
+    }
+    let mut depths = vec![0; slots.len()];
+    let mut depth = self.get_depth(last).unwrap_or(0);
+    for (index, item) in items[..slots.len()].iter().enumerate().rev() {
+      depth = depth.max(self.get_depth(*item).unwrap_or(0));
+      depths[index] = depth;
     }
+    let pointers = self.fill_slots(slots, objects, depths);
+    return Ok(pointers[0]);
   }
-}
 
-struct Thread {
-  frame: Frame,
-}
+  /// Finds the first `count` free slots that `put` would fill, failing
+  /// with `Error::Space` if there aren't that many.
+  fn find_free_slots(&mut self, count: usize) -> Result<Vec<usize>> {
+    self.is_full = false;
+    if self.allocated.saturating_add(count as u64) > self.alloc_limit {
+      return Err(Error::Space);
+    }
+    let start = if self.is_monotonic { self.frontier } else { 0 };
+    let slots: Vec<usize> = self.nodes.iter().enumerate().skip(start)
+      .filter(|(_, maybe_node)| maybe_node.is_none())
+      .map(|(index, _)| index)
+      .take(count)
+      .collect();
+    if slots.len() < count {
+      self.is_full = true;
+      return Err(Error::Space);
+    }
+    return Ok(slots);
+  }
 
-impl Thread {
-  fn with_continuation(continuation: Gc) -> Self {
-    Thread {
-      frame: Frame::new(continuation),
+  fn fill_slots(
+    &mut self,
+    slots: Vec<usize>,
+    objects: Vec<Object>,
+    depths: Vec<usize>) -> Vec<Gc> {
+    let count = slots.len();
+    let mut pointers = Vec::with_capacity(count);
+    let batch = slots.into_iter().zip(objects).zip(depths).enumerate();
+    for (offset, ((index, object), depth)) in batch {
+      // Later objects in a batch may be the children of earlier ones,
+      // so they get the lower serials.
+      let serial = self.allocated + (count - 1 - offset) as u64;
+      let node = Node::new(object, self.generation, serial, depth);
+      self.nodes[index] = Some(node);
+      self.frontier = index + 1;
+      pointers.push(Gc::new(index, self.generation));
+    }
+    self.live += count;
+    self.allocated += count as u64;
+    if self.live > self.peak_live {
+      self.peak_live = self.live;
     }
+    return pointers;
   }
 
-  fn has_continuation(&self) -> bool {
-    return !self.frame.con.is_empty();
+  /// Lists the elements of a sequence, skipping identities.
+  fn get_elements(&self, root: Gc) -> Result<Vec<Gc>> {
+    let mut elements = Vec::new();
+    let mut xs = root;
+    while self.is_sequence(xs)? {
+      let fst = self.get_sequence_fst(xs)?;
+      if !self.is_id(fst)? {
+        elements.push(self.follow(xs, fst)?);
+      }
+      xs = self.follow(xs, self.get_sequence_snd(xs)?)?;
+    }
+    if !self.is_id(xs)? {
+      elements.push(xs);
+    }
+    return Ok(elements);
   }
 
-  fn get_continuation(
-    &mut self, heap: &mut Heap) -> Result<Gc> {
-    let mut xs = heap.new_id()?;
-    for object in self.frame.con.iter() {
-      xs = heap.new_sequence(*object, xs)?;
+  /// Copies a term into fresh nodes of the same heap, so that nothing
+  /// done to the copy in place can affect the original. Structure
+  /// shared within the term is shared within the copy too.
+  fn clone_term(&mut self, root: Gc) -> Result<Gc> {
+    let mut done: HashMap<Gc, Gc> = HashMap::new();
+    let mut stack = vec![(root, false)];
+    while let Some((pointer, is_ready)) = stack.pop() {
+      if done.contains_key(&pointer) {
+        continue;
+      }
+      let object = match self.get_ref(pointer)?.clone() {
+        Object::Block(body) => {
+          if !is_ready {
+            stack.push((pointer, true));
+            stack.push((self.follow(pointer, body)?, false));
+            continue;
+          }
+          Object::Block(done[&body])
+        }
+        Object::Sequence(fst, snd) => {
+          if !is_ready {
+            stack.push((pointer, true));
+            stack.push((self.follow(pointer, snd)?, false));
+            stack.push((self.follow(pointer, fst)?, false));
+            continue;
+          }
+          Object::Sequence(done[&fst], done[&snd])
+        }
+        object => {
+          object
+        }
+      };
+      let copy = self.put(object)?;
+      done.insert(pointer, copy);
     }
-    self.frame.con.clear();
-    return Ok(xs);
+    return done.get(&root).copied().ok_or(Error::Bug);
   }
 
-  fn push_continuation_front(&mut self, data: Gc) {
-    self.frame.con.push_front(data);
+  /// Rebuilds a term without the identities buried in its sequences
+  /// or its blocks' bodies. Parts of the term that are already compact
+  /// are reused rather than copied, as is any shared structure.
+  fn compact_term(&mut self, root: Gc) -> Result<Gc> {
+    let mut done: HashMap<Gc, Gc> = HashMap::new();
+    let mut values: Vec<Gc> = Vec::new();
+    let mut stack = vec![Compact::Visit(root)];
+    while let Some(task) = stack.pop() {
+      match task {
+        Compact::Visit(pointer) => {
+          if let Some(value) = done.get(&pointer) {
+            values.push(*value);
+            continue;
+          }
+          match self.get_ref(pointer)? {
+            &Object::Block(body) => {
+              stack.push(Compact::Block(pointer));
+              stack.push(Compact::Visit(self.follow(pointer, body)?));
+            }
+            &Object::Sequence(_, _) => {
+              let elements = self.get_elements(pointer)?;
+              stack.push(Compact::Sequence(pointer, elements.len()));
+              for element in elements.iter().rev() {
+                stack.push(Compact::Visit(*element));
+              }
+            }
+            _ => {
+              values.push(pointer);
+            }
+          }
+        }
+        Compact::Block(pointer) => {
+          let body = values.pop().ok_or(Error::Bug)?;
+          let value = if body == self.get_block_body(pointer)? {
+            pointer
+          } else {
+            self.new_block(body)?
+          };
+          done.insert(pointer, value);
+          values.push(value);
+        }
+        Compact::Sequence(pointer, count) => {
+          let start = values.len().checked_sub(count).ok_or(Error::Bug)?;
+          let elements = values.split_off(start);
+          let value = if self.is_compact(pointer, &elements)? {
+            pointer
+          } else {
+            let mut xs = self.new_id()?;
+            for element in elements.iter().rev() {
+              xs = self.new_sequence(*element, xs)?;
+            }
+            xs
+          };
+          done.insert(pointer, value);
+          values.push(value);
+        }
+      }
+    }
+    return values.pop().ok_or(Error::Bug);
   }
 
-  fn push_continuation_back(&mut self, data: Gc) {
-    self.frame.con.push_back(data);
+  /// Whether a sequence is a chain of the given elements, with no
+  /// identities and no sequences among them.
+  fn is_compact(&self, root: Gc, elements: &[Gc]) -> Result<bool> {
+    let mut xs = root;
+    let mut index = 0;
+    while self.is_sequence(xs)? {
+      let fst = self.get_sequence_fst(xs)?;
+      if elements.get(index) != Some(&fst) || self.is_sequence(fst)? {
+        return Ok(false);
+      }
+      index += 1;
+      xs = self.get_sequence_snd(xs)?;
+    }
+    return Ok(elements.get(index) == Some(&xs) && index + 1 == elements.len());
   }
 
-  fn pop_continuation(
-    &mut self, heap: &mut Heap) -> Result<Gc> {
-    loop {
-      let code = self.frame.con.pop_front().ok_or(Error::Bug)?;
-      if heap.is_sequence(code)? {
-        let fst = heap.get_sequence_fst(code)?;
-        let snd = heap.get_sequence_snd(code)?;
-        self.frame.con.push_front(snd);
-        self.frame.con.push_front(fst);
-      } else {
-        return Ok(code);
+  /// Quotes a term, collapsing adjacent duplicate blocks: a block that
+  /// repeats the one just before it is written as `d`, so that `[A]
+  /// [A]` becomes `[A] d`. Repeats that aren't side by side are written
+  /// out in full, and blocks' bodies are left alone, since rewriting
+  /// them would change the blocks the source produces.
+  fn collapse_duplicates(&self, root: Gc) -> Result<String> {
+    let mut target = String::new();
+    let mut previous: Option<String> = None;
+    let mut elements = self.get_elements(root)?;
+    elements.reverse();
+    while let Some(element) = elements.pop() {
+      let src = match self.get_ref(element)? {
+        &Object::Sequence(_, _) => {
+          let mut inner = self.get_elements(element)?;
+          inner.reverse();
+          elements.append(&mut inner);
+          continue;
+        }
+        &Object::Block(_) => {
+          let src = self.to_source(element)?;
+          if previous.as_ref() == Some(&src) {
+            "d".to_string()
+          } else {
+            previous = Some(src.clone());
+            src
+          }
+        }
+        _ => {
+          previous = None;
+          self.to_source(element)?
+        }
+      };
+      if !target.is_empty() {
+        target.push(' ');
       }
+      target.push_str(&src);
     }
+    return Ok(target);
   }
 
-  fn is_monadic(&self) -> bool {
-    return self.frame.env.len() >= 1;
+  /// Rebuilds a term with some redundant code taken out, here and in
+  /// the bodies of its blocks, until there is none left:
+  ///
+  /// - `b a` and `d e` and `f f` do nothing.
+  /// - `d f` is `d`, since swapping two copies changes nothing.
+  /// - `[A] e` does nothing, since a block has no effects.
+  /// - `[] a` does nothing.
+  ///
+  /// Each of these holds whenever the opcodes have the blocks they
+  /// need, so the simpler term reduces to the same result. It may get
+  /// stuck differently on a stack that is too short.
+  fn simplify(&mut self, root: Gc) -> Result<Gc> {
+    let mut elements = self.get_elements(root)?;
+    elements.reverse();
+    let mut target: Vec<Gc> = Vec::new();
+    while let Some(element) = elements.pop() {
+      let element = match self.get_ref(element)? {
+        &Object::Sequence(_, _) => {
+          let mut inner = self.get_elements(element)?;
+          inner.reverse();
+          elements.append(&mut inner);
+          continue;
+        }
+        &Object::Block(body) => {
+          let body = self.simplify(self.follow(element, body)?)?;
+          self.new_block(body)?
+        }
+        _ => {
+          element
+        }
+      };
+      target.push(element);
+      while target.len() >= 2 {
+        let snd = target[target.len() - 1];
+        let fst = target[target.len() - 2];
+        if !self.is_opcode(snd)? {
+          break;
+        }
+        let rhs = self.get_opcode(snd)?;
+        if self.is_block(fst)? && rhs == Opcode::Drop {
+          target.truncate(target.len() - 2);
+          continue;
+        }
+        if self.is_block(fst)? && rhs == Opcode::App {
+          let body = self.get_block_body(fst)?;
+          if self.get_elements(body)?.is_empty() {
+            target.truncate(target.len() - 2);
+            continue;
+          }
+        }
+        if !self.is_opcode(fst)? {
+          break;
+        }
+        match (self.get_opcode(fst)?, rhs) {
+          (Opcode::Box, Opcode::App) |
+          (Opcode::Copy, Opcode::Drop) |
+          (Opcode::Swap, Opcode::Swap) => {
+            target.truncate(target.len() - 2);
+          }
+          (Opcode::Copy, Opcode::Swap) => {
+            target.pop();
+          }
+          _ => {
+            break;
+          }
+        }
+      }
+    }
+    let mut xs = self.new_id()?;
+    for object in target.iter().rev() {
+      xs = self.new_sequence(*object, xs)?;
+    }
+    return Ok(xs);
   }
 
-  fn is_dyadic(&self) -> bool {
-    return self.frame.env.len() >= 2;
+  /// Quotes a term into a new string.
+  fn to_source(&self, root: Gc) -> Result<String> {
+    let mut src = String::new();
+    quote(root, self, &mut src)?;
+    return Ok(src);
   }
 
-  fn get_environment(
-    &mut self, heap: &mut Heap) -> Result<Gc> {
-    let mut xs = heap.new_id()?;
-    for object in self.frame.env.iter().rev() {
-      xs = heap.new_sequence(*object, xs)?;
-    }
-    for object in self.frame.err.iter().rev() {
-      xs = heap.new_sequence(*object, xs)?;
+  /// Quotes a term like `to_source`, but writes any block nested more
+  /// than `max_depth` blocks deep as `…`. The result is for reading,
+  /// and doesn't parse back to the same term.
+  fn quote_depth(&self, root: Gc, max_depth: usize) -> Result<String> {
+    let mut buf = String::new();
+    let mut stack = vec![(Quote::Term(root), 0)];
+    while let Some((task, depth)) = stack.pop() {
+      let pointer = match task {
+        Quote::Term(pointer) => {
+          pointer
+        }
+        Quote::Text(value) => {
+          buf.push(value);
+          continue;
+        }
+      };
+      match self.get_ref(pointer)? {
+        &Object::Block(_) if depth >= max_depth => {
+          buf.push('…');
+        }
+        &Object::Block(body) => {
+          buf.push('[');
+          stack.push((Quote::Text(']'), depth));
+          stack.push((Quote::Term(self.follow(pointer, body)?), depth + 1));
+        }
+        &Object::Sequence(fst, snd) => {
+          if !self.is_id(snd)? {
+            stack.push((Quote::Term(self.follow(pointer, snd)?), depth));
+            stack.push((Quote::Text(' '), depth));
+          }
+          stack.push((Quote::Term(self.follow(pointer, fst)?), depth));
+        }
+        _ => {
+          quote(pointer, self, &mut buf)?;
+        }
+      }
     }
-    self.frame.env.clear();
-    self.frame.err.clear();
-    return Ok(xs);
+    return Ok(buf);
   }
 
-  fn push_environment(&mut self, data: Gc) {
-    self.frame.env.push(data);
+  /// Lists every node in the heap, reachable or not, with a description
+  /// like `describe` gives. A node whose subterms have been deleted is
+  /// described by the error met while quoting it.
+  fn live_nodes(&self) -> impl Iterator<Item = (Gc, String)> + '_ {
+    return self.nodes.iter().enumerate()
+      .filter_map(move |(index, maybe_node)| {
+        let node = maybe_node.as_ref()?;
+        let pointer = Gc::new(index, node.generation);
+        let description = self.describe(pointer)
+          .unwrap_or_else(|error| format!("unreadable: {:?}", error));
+        return Some((pointer, description));
+      });
   }
 
-  fn pop_environment(&mut self) -> Result<Gc> {
-    return self.frame.env.pop().ok_or(Error::Underflow);
+  /// Counts the nodes reachable from the given roots, and from the
+  /// heap's own roots and pins, against the nodes that are live. The
+  /// heap is left as it is.
+  #[cfg(test)]
+  fn audit(&self, roots: &[Gc]) -> Result<AuditReport> {
+    let mut visited = std::collections::HashSet::new();
+    let mut stack: Vec<Gc> = roots.to_vec();
+    stack.extend(self.roots.iter());
+    stack.extend(self.pins.iter().flatten());
+    while let Some(pointer) = stack.pop() {
+      if !visited.insert(pointer) {
+        continue;
+      }
+      match self.get_ref(pointer)? {
+        &Object::Block(body) => {
+          stack.push(body);
+        }
+        &Object::Sequence(fst, snd) => {
+          stack.push(fst);
+          stack.push(snd);
+        }
+        _ => {
+          //
+        }
+      }
+    }
+    return Ok(AuditReport {
+      reachable: visited.len(),
+      live: self.live,
+      leaked: self.live.saturating_sub(visited.len()),
+    });
   }
 
-  fn peek_environment(&mut self) -> Result<Gc> {
-    return self.frame.env.last().map(|x| *x).ok_or(Error::Underflow);
+  /// Describes a single element of a term, for diagnostics.
+  fn describe(&self, root: Gc) -> Result<String> {
+    let src = self.to_source(root)?;
+    let kind = match self.get_ref(root)? {
+      &Object::Id => "identity",
+      &Object::Opcode(_) => "opcode",
+      &Object::Word(_) => "word",
+      &Object::Hint(_) => "hint",
+      &Object::Block(_) => "block",
+      &Object::Sequence(_, _) => "sequence",
+    };
+    return Ok(format!("{} `{}`", kind, src));
   }
 
-  fn thunk(&mut self, root: Gc) {
-    self.frame.err.append(&mut self.frame.env);
-    self.frame.err.push(root);
+  /// Describes why a thunk could not make progress.
+  fn explain(&self, thunk: &Thunk) -> Result<String> {
+    let code = thunk.code;
+    if self.is_word(code)? {
+      let value = self.get_word(code)?;
+      return Ok(format!("word `{}` is undefined", value));
+    }
+    let name = self.to_source(code)?;
+    match self.get_opcode(code)?.get_arity() {
+      Some(needed) => {
+        let blocks = if needed == 1 { "block" } else { "blocks" };
+        return Ok(format!(
+          "`{}` at position {} needs {} {} on the stack but found {}",
+          name,
+          thunk.position,
+          count_to_string(needed),
+          blocks,
+          count_to_string(thunk.found)));
+      }
+      None => {
+        return Ok(format!(
+          "`{}` at position {} is a proposition, which never reduces",
+          name,
+          thunk.position));
+      }
+    }
   }
 
-  fn step(
-    &mut self,
-    heap: &mut Heap,
-    tab: &HashMap<Rc<str>, Gc>) -> Result<()> {
-    let code = self.pop_continuation(heap)?;
-    if heap.is_block(code)? {
-      self.push_environment(code);
-    } else if heap.is_opcode(code)? {
-      match heap.get_opcode(code)? {
-        Opcode::App => {
-          if !self.is_monadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          let source = self.pop_environment()?;
-          let target = heap.get_block_body(source)?;
-          self.push_continuation_front(target);
-        }
-        Opcode::Box => {
-          if !self.is_monadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          let source = self.pop_environment()?;
-          let target = heap.new_block(source)?;
-          self.push_environment(target);
-        }
-        Opcode::Cat => {
-          if !self.is_dyadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          let rhs = self.pop_environment()?;
-          let lhs = self.pop_environment()?;
-          let rhs_body = heap.get_block_body(rhs)?;
-          let lhs_body = heap.get_block_body(lhs)?;
-          let target_body = heap.new_sequence(lhs_body, rhs_body)?;
-          let target = heap.new_block(target_body)?;
-          self.push_environment(target);
+  /// Hashes a term's structure, so that terms `diff` finds the same
+  /// hash the same, however their nodes are laid out.
+  fn hash_term(&self, root: Gc) -> Result<u64> {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut stack = vec![Quote::Term(root)];
+    while let Some(task) = stack.pop() {
+      let pointer = match task {
+        Quote::Term(pointer) => {
+          pointer
         }
-        Opcode::Copy => {
-          if !self.is_monadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          let source = self.peek_environment()?;
-          self.push_environment(source);
+        Quote::Text(value) => {
+          value.hash(&mut hasher);
+          continue;
         }
-        Opcode::Drop => {
-          if !self.is_monadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          self.pop_environment()?;
+      };
+      match self.get_ref(pointer)? {
+        &Object::Id => {
+          //
         }
-        Opcode::Swap => {
-          if !self.is_dyadic() {
-            self.thunk(code);
-            return Ok(());
-          }
-          let fst = self.pop_environment()?;
-          let snd = self.pop_environment()?;
-          self.push_environment(fst);
-          self.push_environment(snd);
+        &Object::Opcode(ref value) => {
+          ('o', value.get_name()).hash(&mut hasher);
         }
-        Opcode::Prop | Opcode::Forall => {
-          self.thunk(code);
-          return Ok(());
+        &Object::Word(ref value) => {
+          ('w', &**value).hash(&mut hasher);
         }
-      }
-    } else if heap.is_word(code)? {
-      let code_value = heap.get_word(code)?;
-      match tab.get(&code_value) {
-        Some(binding) => {
-          self.push_continuation_front(*binding);
+        &Object::Hint(ref value) => {
+          ('h', &**value).hash(&mut hasher);
         }
-        None => {
-          self.thunk(code);
+        &Object::Block(body) => {
+          '['.hash(&mut hasher);
+          stack.push(Quote::Text(']'));
+          stack.push(Quote::Term(self.follow(pointer, body)?));
+        }
+        &Object::Sequence(fst, snd) => {
+          stack.push(Quote::Term(self.follow(pointer, snd)?));
+          stack.push(Quote::Term(self.follow(pointer, fst)?));
         }
       }
-      return Ok(());
-    } else if heap.is_id(code)? || heap.is_hint(code)? {
-      return Ok(());
-    } else {
-      return Err(Error::Bug);
     }
-    return Ok(());
+    return Ok(hasher.finish());
   }
-}
 
-pub struct Pod {
-  heap: Heap,
-  tab: Library,
-}
+  /// Describes the first place where two terms differ structurally,
+  /// or returns `None` if they are the same.
+  fn diff(&self, lhs: Gc, rhs: Gc) -> Result<Option<String>> {
+    let mut path = Vec::new();
+    return self.diff_at(lhs, rhs, &mut path);
+  }
 
-impl Pod {
-  fn with_heap(heap: Heap) -> Self {
-    Pod {
-      heap: heap,
-      tab: HashMap::new(),
+  fn diff_at(
+    &self,
+    lhs: Gc,
+    rhs: Gc,
+    path: &mut Vec<usize>) -> Result<Option<String>> {
+    let lhs_elements = self.get_elements(lhs)?;
+    let rhs_elements = self.get_elements(rhs)?;
+    let len = std::cmp::max(lhs_elements.len(), rhs_elements.len());
+    for index in 0..len {
+      path.push(index);
+      let expected = lhs_elements.get(index);
+      let found = rhs_elements.get(index);
+      let message = match (expected, found) {
+        (Some(&expected), Some(&found)) => {
+          match (self.get_ref(expected)?, self.get_ref(found)?) {
+            (&Object::Block(lhs_body), &Object::Block(rhs_body)) => {
+              let lhs_body = self.follow(expected, lhs_body)?;
+              let rhs_body = self.follow(found, rhs_body)?;
+              let diff = self.diff_at(lhs_body, rhs_body, path)?;
+              if diff.is_some() {
+                return Ok(diff);
+              }
+              None
+            }
+            (&Object::Opcode(lhs_value), &Object::Opcode(rhs_value))
+              if lhs_value == rhs_value => {
+              None
+            }
+            (&Object::Word(ref lhs_value), &Object::Word(ref rhs_value)) |
+            (&Object::Hint(ref lhs_value), &Object::Hint(ref rhs_value))
+              if lhs_value == rhs_value => {
+              None
+            }
+            _ => {
+              Some((self.describe(expected)?, self.describe(found)?))
+            }
+          }
+        }
+        (Some(&expected), None) => {
+          Some((self.describe(expected)?, "the end of the term".into()))
+        }
+        (None, Some(&found)) => {
+          Some(("the end of the term".into(), self.describe(found)?))
+        }
+        (None, None) => {
+          None
+        }
+      };
+      if let Some((expected, found)) = message {
+        let mut buf = String::from("at position ");
+        for (depth, index) in path.iter().enumerate() {
+          if depth > 0 {
+            buf.push_str(", block body position ");
+          }
+          buf.push_str(&index.to_string());
+        }
+        buf.push_str(&format!(": expected {}, found {}", expected, found));
+        return Ok(Some(buf));
+      }
+      path.pop();
     }
+    return Ok(None);
   }
 
-  pub fn from_string(
-    src: &str,
-    space_quota: usize,
-    time_quota: u64) -> Result<Self> {
-    let heap = Heap::with_capacity(space_quota);
-    let mut pod = Pod::with_heap(heap);
-    for line in src.lines() {
-      pod.eval(line, time_quota)?;
+  /// Dereferences a pointer, failing with `Error::Null` if it is
+  /// stale or out of range.
+  fn get_ref(&self, pointer: Gc) -> Result<&Object> {
+    match self.nodes.get(pointer.index) {
+      Some(&Some(ref node)) => {
+        if node.generation == pointer.generation {
+          return Ok(&node.object);
+        }
+        return Err(Error::Null);
+      }
+      _ => {
+        return Err(Error::Null);
+      }
     }
-    return Ok(pod);
   }
 
-  pub fn default(space_quota: usize, time_quota: u64) -> Result<Self> {
-    let home = std::env::var("SUNDIAL_HOME").or(Err(Error::Home))?;
-    let path: std::path::PathBuf = [&home, "pod", "default.md"].iter().collect();
-    let src = std::fs::read_to_string(path).or(Err(Error::Home))?;
-    return Pod::from_string(&src, space_quota, time_quota);
+  /// Whether a pointer still refers to the node it was made for. A
+  /// pointer that is live now may not be after the next sweep.
+  #[allow(dead_code)]
+  fn is_live(&self, pointer: Gc) -> bool {
+    match self.nodes.get(pointer.index) {
+      Some(&Some(ref node)) => {
+        return node.generation == pointer.generation;
+      }
+      _ => {
+        return false;
+      }
+    }
   }
 
-  pub fn eval(&mut self, src: &str, time_quota: u64) -> Result<String> {
-    let mut dst = String::new();
-    if let Some(data) = POD_INSERT_REGEX.captures(src) {
-      let key: Rc<str> = data.get(1).expect("key").as_str().into();
-      let value_src = data.get(2).expect("value").as_str();
-      let value = parse(value_src, &mut self.heap)?;
-      let value = reduce(
-        value, &mut self.heap, &self.tab, time_quota)?;
-      self.tab.insert(key.clone(), value);
-      dst.push(':');
-      dst.push_str(&key);
-      dst.push(' ');
-      quote(value, &mut self.heap, &mut dst)?;
-    } else if let Some(data) = POD_DELETE_REGEX.captures(src) {
-      let key: Rc<str> = data.get(1).expect("key").as_str().into();
-      self.tab.remove(&key);
-      dst.push('~');
-      dst.push_str(&key);
-    } else {
-      let source = parse(src, &mut self.heap)?;
-      let target = reduce(
-        source, &mut self.heap, &self.tab, time_quota)?;
-      quote(target, &mut self.heap, &mut dst)?;
+  /// The generation new nodes are allocated in. It changes with every
+  /// sweep, so a cache can compare it to tell when to revalidate.
+  fn generation(&self) -> u64 {
+    return self.generation;
+  }
+}
+
+/// Spells out a small count for use in a message.
+fn count_to_string(count: usize) -> String {
+  match count {
+    0 => {
+      return "none".to_string();
     }
-    for pointer in self.tab.values() {
-      self.heap.mark(*pointer)?;
+    1 => {
+      return "one".to_string();
+    }
+    2 => {
+      return "two".to_string();
+    }
+    3 => {
+      return "three".to_string();
+    }
+    _ => {
+      return count.to_string();
     }
-    self.heap.sweep()?;
-    return Ok(dst);
   }
+}
 
-  pub fn to_string(&self) -> Result<String> {
-    let mut target = String::new();
-    let mut keys: Vec<Rc<str>> = self.tab.keys()
-      .map(|x| x.clone()).collect();
+/// Splits a pod source into records. A record is usually one line,
+/// but a `:word` line continues onto any indented lines that follow
+/// it, up to the next blank or unindented line.
+fn records(src: &str) -> Vec<String> {
+  return numbered_records(src).into_iter()
+    .map(|(_, record)| record)
+    .collect();
+}
+
+/// Splits a pod source into records like `records`, pairing each with
+/// the number of the line it starts on, counting from 1.
+fn numbered_records(src: &str) -> Vec<(usize, String)> {
+  let mut records: Vec<(usize, String)> = Vec::new();
+  let mut is_open = false;
+  for (index, line) in src.lines().enumerate() {
+    let is_indented = line.starts_with(|x: char| x.is_whitespace());
+    if is_open && is_indented && !line.trim().is_empty() {
+      let (_, record) = records.last_mut().expect("record");
+      record.push(' ');
+      record.push_str(line.trim());
+      continue;
+    }
+    is_open = line.starts_with(':');
+    records.push((index + 1, line.to_string()));
+  }
+  return records;
+}
+
+/// Orders the definitions in a split library so that each comes after
+/// the definitions it mentions, where that's possible. Definitions
+/// that mention one another are taken in order of their names.
+fn dependency_order(sources: &HashMap<String, String>) -> Vec<String> {
+  let mut keys: Vec<&String> = sources.keys().collect();
+  keys.sort();
+  let mut order = Vec::new();
+  let mut seen = std::collections::HashSet::new();
+  for root in keys {
+    let mut stack = vec![(root, false)];
+    while let Some((key, is_expanded)) = stack.pop() {
+      if is_expanded {
+        order.push(key.to_string());
+        continue;
+      }
+      if !seen.insert(key) {
+        continue;
+      }
+      stack.push((key, true));
+      let mut dependencies: Vec<&String> = tokenize(&sources[key]).iter()
+        .filter_map(|(_, word)| sources.get_key_value(*word))
+        .map(|(dependency, _)| dependency)
+        .filter(|dependency| !seen.contains(dependency))
+        .collect();
+      dependencies.sort();
+      dependencies.dedup();
+      for dependency in dependencies.into_iter().rev() {
+        stack.push((dependency, false));
+      }
+    }
+  }
+  return order;
+}
+
+/// Splits a program into statements, which end at a `;` or a line
+/// break. Neither ends a statement inside brackets or a quoted doc,
+/// so `[;]` is a block holding the word `;`. Blank statements are
+/// left out.
+fn statements(src: &str) -> Vec<&str> {
+  let mut statements = Vec::new();
+  let mut depth: usize = 0;
+  let mut is_quoted = false;
+  let mut start = 0;
+  for (index, x) in src.char_indices() {
+    match x {
+      '"' => {
+        is_quoted = !is_quoted;
+      }
+      '[' if !is_quoted => {
+        depth += 1;
+      }
+      ']' if !is_quoted => {
+        depth = depth.saturating_sub(1);
+      }
+      ';' | '\n' if !is_quoted && depth == 0 => {
+        statements.push(&src[start..index]);
+        start = index + 1;
+      }
+      _ => {
+        //
+      }
+    }
+  }
+  statements.push(&src[start..]);
+  statements.retain(|statement| !statement.trim().is_empty());
+  return statements;
+}
+
+/// Splits source text into tokens, each with its byte offset. Tokens
+/// are separated by whitespace, and each bracket is a token of its
+/// own, so `[A][B]c` has the same tokens as `[A] [B] c`.
+fn tokenize(src: &str) -> Vec<(usize, &str)> {
+  let mut tokens = Vec::new();
+  let mut start: Option<usize> = None;
+  for (index, x) in src.char_indices() {
+    let is_bracket = x == '[' || x == ']';
+    if x.is_whitespace() || is_bracket {
+      if let Some(offset) = start.take() {
+        tokens.push((offset, &src[offset..index]));
+      }
+    }
+    if is_bracket {
+      tokens.push((index, &src[index..index + 1]));
+    } else if !x.is_whitespace() {
+      start.get_or_insert(index);
+    }
+  }
+  if let Some(offset) = start {
+    tokens.push((offset, &src[offset..]));
+  }
+  return tokens;
+}
+
+/// Whether a token is made only of opcode letters.
+fn is_opcode_run(word: &str) -> bool {
+  return word.chars().all(|x| Opcode::from_name(x).is_some());
+}
+
+/// Whether `parse` would read a token as something other than a word:
+/// a single lowercase letter is always an opcode or reserved for one.
+fn is_reserved(word: &str, config: &Config) -> bool {
+  if config.opcode_runs && is_opcode_run(word) {
+    return true;
+  }
+  return word.len() == 1 && word.chars().all(|x| x.is_lowercase());
+}
+
+/// Checks source text the way `parse` does, failing with the same
+/// error, but without building a term.
+pub fn validate(src: &str, config: &Config) -> Result<()> {
+  let mut depth = 0;
+  for (_, word) in tokenize(src) {
+    match word {
+      "[" => {
+        if depth >= config.nesting_quota {
+          return Err(Error::Space);
+        }
+        depth += 1;
+      }
+      "]" => {
+        if depth == 0 {
+          return Err(Error::Syntax);
+        }
+        depth -= 1;
+      }
+      _ => {
+        if word.len() == 1 && is_opcode_run(word) {
+          continue;
+        }
+        if config.opcode_runs && is_opcode_run(word) {
+          continue;
+        }
+        if is_reserved(word, config) {
+          return Err(Error::Syntax);
+        }
+      }
+    }
+  }
+  if depth > 0 {
+    return Err(Error::Syntax);
+  }
+  return Ok(());
+}
+
+/// Parses source text into a term. Fails with `Error::Space` if more
+/// brackets are open at once than the config allows.
+///
+/// A single lowercase letter is an opcode, or reserved for one. Any
+/// other token that isn't a bracket or a hint is a word, so a single
+/// uppercase letter such as `A` is always a word. Since definitions
+/// are named in lowercase, such a word can never be bound, which makes
+/// it a safe placeholder for an arbitrary block.
+fn parse(src: &str, heap: &mut Heap, config: &Config) -> Result<Gc> {
+  let mut state = ParseState::new();
+  state.feed(src, heap, config)?;
+  return state.finish(heap, config);
+}
+
+/// A term that is partly parsed, so that source text arriving a piece
+/// at a time can be parsed as it comes rather than all over again. A
+/// bracket may open in one piece and close in another, and a token
+/// cut in two is joined back up.
+///
+/// The nodes built so far aren't roots, so the heap mustn't be
+/// collected until the term is finished.
+struct ParseState {
+  /// The terms parsed so far in the innermost open block.
+  build: Vec<Gc>,
+  /// The terms parsed so far in each enclosing block.
+  stack: Vec<Vec<Gc>>,
+  /// The end of the last piece, which may be the start of a token.
+  partial: String,
+}
+
+impl ParseState {
+  fn new() -> Self {
+    ParseState {
+      build: vec![],
+      stack: vec![],
+      partial: String::new(),
+    }
+  }
+
+  /// Parses another piece of source text, as if it followed the
+  /// pieces already fed in.
+  fn feed(
+    &mut self, src: &str, heap: &mut Heap, config: &Config) -> Result<()> {
+    let mut text = std::mem::take(&mut self.partial);
+    text.push_str(src);
+    let is_cut = match text.chars().last() {
+      Some(x) => !x.is_whitespace() && x != '[' && x != ']',
+      None => false,
+    };
+    let mut tokens = tokenize(&text);
+    if is_cut {
+      if let Some((_, word)) = tokens.pop() {
+        self.partial = word.to_string();
+      }
+    }
+    for (_, word) in tokens {
+      self.push_token(word, heap, config)?;
+    }
+    return Ok(());
+  }
+
+  fn push_token(
+    &mut self, word: &str, heap: &mut Heap, config: &Config) -> Result<()> {
+    match word {
+      "[" => {
+        if self.stack.len() >= config.nesting_quota {
+          return Err(Error::Space);
+        }
+        let build = std::mem::take(&mut self.build);
+        self.stack.push(build);
+      }
+      "]" => {
+        let prev = self.stack.pop().ok_or(Error::Syntax)?;
+        let body = heap.new_list(&self.build)?;
+        let xs = heap.new_block(body)?;
+        self.build = prev;
+        self.build.push(xs);
+      }
+      "a" => {
+        let opcode = Opcode::App;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "b" => {
+        let opcode = Opcode::Box;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "c" => {
+        let opcode = Opcode::Cat;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "d" => {
+        let opcode = Opcode::Copy;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "e" => {
+        let opcode = Opcode::Drop;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "f" => {
+        let opcode = Opcode::Swap;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "g" => {
+        let opcode = Opcode::Forall;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "h" => {
+        let opcode = Opcode::Prop;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "i" => {
+        let opcode = Opcode::Keep;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "j" => {
+        let opcode = Opcode::Append;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "k" => {
+        let opcode = Opcode::Rot;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      "l" => {
+        let opcode = Opcode::Isolate;
+        let object = heap.new_opcode(opcode)?;
+        self.build.push(object);
+      }
+      _ => {
+        if config.opcode_runs && is_opcode_run(word) {
+          let mut objects = Vec::with_capacity(word.len());
+          for name in word.chars() {
+            let opcode = Opcode::from_name(name).ok_or(Error::Bug)?;
+            objects.push(Object::Opcode(opcode));
+          }
+          self.build.extend(heap.put_many(objects)?);
+          return Ok(());
+        }
+        if is_reserved(word, config) {
+          return Err(Error::Syntax);
+        }
+        if let Some(data) = HINT_REGEX.captures(word) {
+          let name = data.get(1).ok_or(Error::Bug)?.as_str();
+          let object = heap.new_hint(name.into())?;
+          self.build.push(object);
+        } else {
+          let object = heap.new_word(word.into())?;
+          self.build.push(object);
+        }
+      }
+    }
+    return Ok(());
+  }
+
+  /// Parses what is left of the last piece, and builds the term.
+  /// Fails with `Error::Syntax` if a bracket is still open.
+  fn finish(mut self, heap: &mut Heap, config: &Config) -> Result<Gc> {
+    if !self.partial.is_empty() {
+      let word = std::mem::take(&mut self.partial);
+      self.push_token(&word, heap, config)?;
+    }
+    if !self.stack.is_empty() {
+      return Err(Error::Syntax);
+    }
+    return heap.new_list(&self.build);
+  }
+}
+
+/// A pending piece of work while compacting a term.
+enum Compact {
+  Visit(Gc),
+  /// Rebuild a block from the compacted body on top of the stack.
+  Block(Gc),
+  /// Rebuild a sequence from the given number of compacted elements.
+  Sequence(Gc, usize),
+}
+
+/// A pending piece of work while quoting a term.
+enum Quote {
+  Term(Gc),
+  Text(char),
+}
+
+fn quote(root: Gc, heap: &Heap, buf: &mut String) -> Result<()> {
+  let mut stack = vec![Quote::Term(root)];
+  while let Some(task) = stack.pop() {
+    match task {
+      Quote::Term(pointer) => {
+        quote_object(pointer, heap, buf, &mut stack)?;
+      }
+      Quote::Text(value) => {
+        buf.push(value);
+      }
+    }
+  }
+  return Ok(());
+}
+
+/// Quotes a term like `quote`, writing it out a piece at a time rather
+/// than building the whole string in memory.
+fn quote_to_writer<W: std::io::Write>(
+  root: Gc, heap: &Heap, writer: &mut W) -> Result<()> {
+  let mut buf = String::new();
+  let mut stack = vec![Quote::Term(root)];
+  while let Some(task) = stack.pop() {
+    buf.clear();
+    match task {
+      Quote::Term(pointer) => {
+        quote_object(pointer, heap, &mut buf, &mut stack)?;
+      }
+      Quote::Text(value) => {
+        buf.push(value);
+      }
+    }
+    writer.write_all(buf.as_bytes()).or(Err(Error::Io))?;
+  }
+  return Ok(());
+}
+
+/// Where `quote_styled` puts spaces. The default is the spacing
+/// `quote` uses.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct QuoteStyle {
+  /// Whether there is a space between a block and whatever is next to
+  /// it, as in `[A] [B] c` rather than `[A][B]c`. Two words or opcodes
+  /// side by side always have a space between them, or they would be
+  /// read back as one token.
+  pub space_in_sequence: bool,
+  /// Whether there is a space just inside each bracket of a block that
+  /// isn't empty, as in `[ A ]` rather than `[A]`.
+  pub space_in_block: bool,
+}
+
+impl Default for QuoteStyle {
+  fn default() -> Self {
+    QuoteStyle {
+      space_in_sequence: true,
+      space_in_block: false,
+    }
+  }
+}
+
+/// A piece of quoted source, as far as spacing is concerned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Piece {
+  Open,
+  Close,
+  Atom,
+}
+
+/// Quotes a term like `quote`, spacing it out as the style says. The
+/// output parses back to the same term whatever the style.
+fn quote_styled(
+  root: Gc, heap: &Heap, style: &QuoteStyle, buf: &mut String) -> Result<()> {
+  let mut stack = vec![Quote::Term(root)];
+  let mut last: Option<Piece> = None;
+  while let Some(task) = stack.pop() {
+    let (piece, text) = match task {
+      Quote::Text(value) => {
+        (Piece::Close, value.to_string())
+      }
+      Quote::Term(pointer) => {
+        match heap.get_ref(pointer)? {
+          &Object::Id => {
+            continue;
+          }
+          &Object::Opcode(ref value) => {
+            (Piece::Atom, value.get_name().to_string())
+          }
+          &Object::Word(ref value) => {
+            (Piece::Atom, value.to_string())
+          }
+          &Object::Hint(ref value) => {
+            (Piece::Atom, format!("({})", value))
+          }
+          &Object::Block(body) => {
+            stack.push(Quote::Text(']'));
+            stack.push(Quote::Term(heap.follow(pointer, body)?));
+            (Piece::Open, "[".to_string())
+          }
+          &Object::Sequence(fst, snd) => {
+            stack.push(Quote::Term(heap.follow(pointer, snd)?));
+            stack.push(Quote::Term(heap.follow(pointer, fst)?));
+            continue;
+          }
+        }
+      }
+    };
+    let is_spaced = match (last, piece) {
+      (None, _) | (Some(Piece::Open), Piece::Close) => false,
+      (Some(Piece::Open), _) | (_, Piece::Close) => style.space_in_block,
+      (Some(Piece::Atom), Piece::Atom) => true,
+      _ => style.space_in_sequence,
+    };
+    if is_spaced {
+      buf.push(' ');
+    }
+    buf.push_str(&text);
+    last = Some(piece);
+  }
+  return Ok(());
+}
+
+fn quote_object(
+  root: Gc,
+  heap: &Heap,
+  buf: &mut String,
+  stack: &mut Vec<Quote>) -> Result<()> {
+  match heap.get_ref(root)? {
+    &Object::Id => {
+      //
+    }
+    &Object::Opcode(ref value) => {
+      buf.push(value.get_name());
+    }
+    &Object::Word(ref value) => {
+      buf.push_str(value);
+    }
+    &Object::Hint(ref value) => {
+      buf.push('(');
+      buf.push_str(value);
+      buf.push(')');
+    }
+    &Object::Block(body) => {
+      buf.push('[');
+      stack.push(Quote::Text(']'));
+      stack.push(Quote::Term(heap.follow(root, body)?));
+    }
+    &Object::Sequence(fst, snd) => {
+      if !heap.is_id(snd)? {
+        stack.push(Quote::Term(heap.follow(root, snd)?));
+        stack.push(Quote::Text(' '));
+      }
+      stack.push(Quote::Term(heap.follow(root, fst)?));
+    }
+  }
+  return Ok(());
+}
+
+/// The outcome of running a thread until it halts or exhausts its
+/// time quota.
+struct Reduction {
+  term: Gc,
+  /// The code that got stuck, if the thread was asked to keep it apart
+  /// from `term`.
+  stuck: Option<Gc>,
+  steps: u64,
+  thunks: Vec<Thunk>,
+  is_exhausted: bool,
+  trace: Vec<TraceEvent>,
+  /// The quoted term after each step, if the thread took snapshots.
+  snapshots: Vec<String>,
+}
+
+/// A step taken by a thread that was recording its work.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceEvent {
+  /// The code that was run, quoted: an opcode, a word that was
+  /// expanded or got stuck, or a block that was pushed. Under the
+  /// innermost strategy, `]` marks the end of a block's body.
+  pub code: String,
+  /// The number of blocks on the stack after the step.
+  pub depth: usize,
+}
+
+impl std::fmt::Display for TraceEvent {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    return write!(f, "{} {}", self.depth, self.code);
+  }
+}
+
+impl std::str::FromStr for TraceEvent {
+  type Err = Error;
+
+  fn from_str(src: &str) -> Result<Self> {
+    let (depth, code) = src.split_once(' ').ok_or(Error::Syntax)?;
+    return Ok(TraceEvent {
+      code: code.to_string(),
+      depth: depth.parse().or(Err(Error::Syntax))?,
+    });
+  }
+}
+
+/// A record of some code that could not make progress.
+#[derive(Debug, Copy, Clone)]
+struct Thunk {
+  code: Gc,
+  /// The position of the code in the reduced term, or in the body of
+  /// the block being reduced under the innermost strategy.
+  position: usize,
+  /// The number of blocks on the stack when the code ran.
+  found: usize,
+}
+
+/// Reduces a term against a library. If the heap fills up, garbage
+/// is collected and the failed step retried once, so any pointer the
+/// caller holds must be reachable from `tab` or the term itself.
+fn reduce(
+  continuation: Gc,
+  heap: &mut Heap,
+  tab: &Library,
+  config: &Config,
+  time_quota: u64) -> Result<Reduction> {
+  let thread = Thread::with_continuation(continuation, config);
+  return run_thread(thread, heap, tab, time_quota);
+}
+
+/// Reduces a term like `reduce`, recording each step in the trace.
+fn reduce_recording(
+  continuation: Gc,
+  heap: &mut Heap,
+  tab: &Library,
+  config: &Config,
+  time_quota: u64) -> Result<Reduction> {
+  let mut thread = Thread::with_continuation(continuation, config);
+  thread.trace = Some(vec![]);
+  return run_thread(thread, heap, tab, time_quota);
+}
+
+/// Reduces a term like `reduce`, quoting the whole term after each
+/// step.
+fn reduce_snapshotting(
+  continuation: Gc,
+  heap: &mut Heap,
+  tab: &Library,
+  config: &Config,
+  time_quota: u64) -> Result<Reduction> {
+  let mut thread = Thread::with_continuation(continuation, config);
+  thread.snapshots = Some(vec![]);
+  return run_thread(thread, heap, tab, time_quota);
+}
+
+/// Reduces a term like `reduce`, but keeps the code that got stuck out
+/// of the reduced term, in `Reduction::stuck`.
+fn reduce_split(
+  continuation: Gc,
+  heap: &mut Heap,
+  tab: &Library,
+  config: &Config,
+  time_quota: u64) -> Result<Reduction> {
+  let mut thread = Thread::with_continuation(continuation, config);
+  thread.is_split = true;
+  return run_thread(thread, heap, tab, time_quota);
+}
+
+/// Runs a thread, limiting its allocations to the config's quota.
+fn run_thread(
+  thread: Thread<'_>,
+  heap: &mut Heap,
+  tab: &Library,
+  time_quota: u64) -> Result<Reduction> {
+  heap.alloc_limit = match thread.config.alloc_quota {
+    Some(alloc_quota) => heap.allocated.saturating_add(alloc_quota),
+    None => u64::MAX,
+  };
+  let reduction = drive_thread(thread, heap, tab, time_quota);
+  heap.alloc_limit = u64::MAX;
+  return reduction;
+}
+
+fn drive_thread(
+  mut thread: Thread<'_>,
+  heap: &mut Heap,
+  tab: &Library,
+  time_quota: u64) -> Result<Reduction> {
+  let steps = run_steps(&mut thread, heap, tab, time_quota)?;
+  let is_exhausted = thread.has_continuation();
+  let (term, stuck) = match thread.get_output(heap) {
+    Err(Error::Space) if heap.is_full => {
+      thread.collect(heap, tab)?;
+      thread.get_output(heap)?
+    }
+    result => {
+      result?
+    }
+  };
+  return Ok(Reduction {
+    term: term,
+    stuck: stuck,
+    steps: steps,
+    thunks: thread.thunks,
+    is_exhausted: is_exhausted,
+    trace: thread.trace.unwrap_or_default(),
+    snapshots: thread.snapshots.unwrap_or_default(),
+  });
+}
+
+/// Steps a thread until it halts or its time quota runs out, leaving
+/// it as it is at that point. Returns the fuel used. A step that finds
+/// the heap full is retried once after collecting garbage, but one
+/// that exceeds a quota fails straight away.
+fn run_steps(
+  thread: &mut Thread<'_>,
+  heap: &mut Heap,
+  tab: &Library,
+  time_quota: u64) -> Result<u64> {
+  let mut time_quota = time_quota;
+  let mut steps = 0;
+  let mut warn_at = match thread.low_fuel {
+    Some((threshold, _)) => time_quota.saturating_sub(threshold),
+    None => u64::MAX,
+  };
+  while steps < time_quota && thread.has_continuation() {
+    if steps >= warn_at {
+      let (threshold, on_low_fuel) = thread.low_fuel.as_mut()
+        .ok_or(Error::Bug)?;
+      match on_low_fuel(steps) {
+        Fuel::Continue(extra) => {
+          time_quota = time_quota.saturating_add(extra);
+          // Ask again once the fuel left is back down to the threshold
+          // or, if a small grant didn't lift it above, once it's spent.
+          warn_at = time_quota.saturating_sub(*threshold)
+            .max(steps.saturating_add(extra.max(1)));
+        }
+        Fuel::Stop => {
+          break;
+        }
+      }
+    }
+    match thread.step(heap, tab) {
+      Err(Error::Space) if heap.is_full => {
+        thread.collect(heap, tab)?;
+        thread.step(heap, tab)?;
+      }
+      result => {
+        result?;
+      }
+    }
+    if thread.snapshots.is_some() {
+      let snapshot = match thread.get_snapshot(heap) {
+        Err(Error::Space) if heap.is_full => {
+          thread.collect(heap, tab)?;
+          thread.get_snapshot(heap)?
+        }
+        result => {
+          result?
+        }
+      };
+      thread.snapshots.as_mut().ok_or(Error::Bug)?.push(snapshot);
+    }
+    steps += thread.cost;
+  }
+  return Ok(steps);
+}
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+struct Frame {
+  con: VecDeque<Gc>,
+  env: Vec<Gc>,
+  err: Vec<Gc>,
+  /// Whether the frame runs a block applied by `l`, whose results go
+  /// back onto the suspended frame's stack rather than into a block.
+  is_isolated: bool,
+}
+
+impl Frame {
+  fn new(root: Gc) -> Self {
+    let mut con = VecDeque::new();
+    con.push_back(root);
+    Frame {
+      con: con,
+      env: vec![],
+      err: vec![],
+      is_isolated: false,
+    }
+  }
+
+  /// Builds the term made of the output, the environment and the
+  /// remaining continuation, leaving the frame as it is.
+  fn get_term(&self, heap: &mut Heap) -> Result<Gc> {
+    let mut xs = heap.new_id()?;
+    for object in self.con.iter().rev() {
+      xs = heap.new_sequence(*object, xs)?;
+    }
+    for object in self.env.iter().rev() {
+      xs = heap.new_sequence(*object, xs)?;
+    }
+    for object in self.err.iter().rev() {
+      xs = heap.new_sequence(*object, xs)?;
+    }
+    return Ok(xs);
+  }
+
+  /// Builds the environment and remaining continuation as one term,
+  /// and the output as another, leaving the frame as it is.
+  fn get_split(&self, heap: &mut Heap) -> Result<(Gc, Gc)> {
+    let mut xs = heap.new_id()?;
+    for object in self.con.iter().rev() {
+      xs = heap.new_sequence(*object, xs)?;
+    }
+    for object in self.env.iter().rev() {
+      xs = heap.new_sequence(*object, xs)?;
+    }
+    let mut ys = heap.new_id()?;
+    for object in self.err.iter().rev() {
+      ys = heap.new_sequence(*object, ys)?;
+    }
+    return Ok((xs, ys));
+  }
+
+  /// Writes the frame as a JSON object with `con`, `env` and `err`
+  /// fields, each a list of quoted terms. The continuation is listed
+  /// in the order it will run, and the stacks from bottom to top.
+  fn to_json(&self, heap: &Heap, target: &mut String) -> Result<()> {
+    let fields = [
+      ("con", self.con.iter().copied().collect::<Vec<Gc>>()),
+      ("env", self.env.clone()),
+      ("err", self.err.clone()),
+    ];
+    target.push('{');
+    for (index, (name, terms)) in fields.iter().enumerate() {
+      if index > 0 {
+        target.push(',');
+      }
+      target.push_str(&format!("\"{}\":[", name));
+      for (index, term) in terms.iter().enumerate() {
+        if index > 0 {
+          target.push(',');
+        }
+        push_json_string(&heap.to_source(*term)?, target);
+      }
+      target.push(']');
+    }
+    target.push('}');
+    return Ok(());
+  }
+
+  fn mark(&self, heap: &mut Heap) -> Result<()> {
+    for pointer in self.con.iter() {
+      heap.mark(*pointer)?;
+    }
+    for pointer in self.env.iter() {
+      heap.mark(*pointer)?;
+    }
+    for pointer in self.err.iter() {
+      heap.mark(*pointer)?;
+    }
+    return Ok(());
+  }
+}
+
+/// Writes a string as a JSON string literal.
+fn push_json_string(src: &str, target: &mut String) {
+  target.push('"');
+  for x in src.chars() {
+    match x {
+      '"' => {
+        target.push_str("\\\"");
+      }
+      '\\' => {
+        target.push_str("\\\\");
+      }
+      _ if x.is_control() => {
+        target.push_str(&format!("\\u{:04x}", x as u32));
+      }
+      _ => {
+        target.push(x);
+      }
+    }
+  }
+  target.push('"');
+}
+
+/// A host function called with each word that isn't in the library.
+/// It can use the stack to take arguments and give results, and
+/// returns whether it handled the word; if not, the word is left in
+/// the output. If it fails with `Error::Space`, garbage is collected
+/// and it is called again, so like an allocating opcode it should
+/// push before it pops.
+pub type Handler = dyn FnMut(&str, &mut Stack) -> Result<bool>;
+
+/// The blocks on a thread's stack, as seen by a `Handler`.
+pub struct Stack<'a> {
+  heap: &'a mut Heap,
+  env: &'a mut Vec<Gc>,
+  config: &'a Config,
+}
+
+impl<'a> Stack<'a> {
+  pub fn len(&self) -> usize {
+    return self.env.len();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    return self.env.is_empty();
+  }
+
+  /// Removes the top block, returning its quoted body.
+  pub fn pop(&mut self) -> Result<Option<String>> {
+    match self.env.pop() {
+      Some(block) => {
+        let mut dst = String::new();
+        let body = self.heap.get_block_body(block)?;
+        quote(body, self.heap, &mut dst)?;
+        return Ok(Some(dst));
+      }
+      None => {
+        return Ok(None);
+      }
+    }
+  }
+
+  /// Parses `src` and pushes it as a block.
+  pub fn push(&mut self, src: &str) -> Result<()> {
+    let body = parse(src, self.heap, self.config)?;
+    let block = self.heap.new_block(body)?;
+    self.env.push(block);
+    return Ok(());
+  }
+}
+
+/// What a reduction that is running low on fuel should do next.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Fuel {
+  /// Carry on, with this many more steps added to the time quota.
+  Continue(u64),
+  /// Stop now, as if the time quota had run out.
+  Stop,
+}
+
+/// A host function called when a reduction has only a few steps of
+/// its time quota left, with the number of steps taken so far.
+pub type LowFuel = dyn FnMut(u64) -> Fuel;
+
+struct Thread<'a> {
+  frame: Frame,
+  /// Frames suspended while the body of a block is reduced under the
+  /// innermost strategy.
+  frames: Vec<Frame>,
+  config: Config,
+  thunks: Vec<Thunk>,
+  trace: Option<Vec<TraceEvent>>,
+  snapshots: Option<Vec<String>>,
+  handler: Option<&'a mut Handler>,
+  low_fuel: Option<(u64, &'a mut LowFuel)>,
+  /// Whether the code that got stuck is taken separately from the
+  /// rest of the output.
+  is_split: bool,
+  /// The fuel used by the last step.
+  cost: u64,
+  /// The reduced forms of the block bodies forced so far, under
+  /// call-by-need.
+  forced: HashMap<Gc, Gc>,
+  /// The number of words expanded so far.
+  expansions: u64,
+}
+
+impl<'a> Thread<'a> {
+  fn with_continuation(continuation: Gc, config: &Config) -> Self {
+    Thread {
+      frame: Frame::new(continuation),
+      frames: vec![],
+      config: *config,
+      thunks: vec![],
+      trace: None,
+      snapshots: None,
+      handler: None,
+      low_fuel: None,
+      is_split: false,
+      cost: 1,
+      forced: HashMap::new(),
+      expansions: 0,
+    }
+  }
+
+  fn has_continuation(&self) -> bool {
+    return self.con_len() > 0 || !self.frames.is_empty();
+  }
+
+  /// The number of blocks on the current frame's stack.
+  fn env_len(&self) -> usize {
+    return self.frame.env.len();
+  }
+
+  /// The number of terms waiting to run in the current frame. A
+  /// sequence counts once until it is unfolded.
+  fn con_len(&self) -> usize {
+    return self.frame.con.len();
+  }
+
+  /// The number of terms the current frame has set aside as stuck.
+  fn err_len(&self) -> usize {
+    return self.frame.err.len();
+  }
+
+  /// Takes the environment followed by any remaining continuation.
+  fn get_term(&mut self, heap: &mut Heap) -> Result<Gc> {
+    while !self.frames.is_empty() {
+      self.close_frame(heap)?;
+    }
+    let xs = self.frame.get_term(heap)?;
+    self.frame.con.clear();
+    self.frame.env.clear();
+    self.frame.err.clear();
+    return Ok(xs);
+  }
+
+  /// Takes the output like `get_term`, but if the thread is split,
+  /// returns the code that got stuck as a second term.
+  fn get_output(&mut self, heap: &mut Heap) -> Result<(Gc, Option<Gc>)> {
+    if !self.is_split {
+      return Ok((self.get_term(heap)?, None));
+    }
+    while !self.frames.is_empty() {
+      self.close_frame(heap)?;
+    }
+    let (xs, ys) = self.frame.get_split(heap)?;
+    self.frame.con.clear();
+    self.frame.env.clear();
+    self.frame.err.clear();
+    return Ok((xs, Some(ys)));
+  }
+
+  /// Quotes the term the thread is reducing, as it stands: any block
+  /// being reduced under the innermost strategy is shown in place.
+  fn get_snapshot(&self, heap: &mut Heap) -> Result<String> {
+    let mut term = self.frame.get_term(heap)?;
+    let mut is_isolated = self.frame.is_isolated;
+    for frame in self.frames.iter().rev() {
+      let mut frame = frame.clone();
+      frame.env.push(heap.new_block(term)?);
+      if is_isolated {
+        frame.con.push_front(heap.new_opcode(Opcode::Isolate)?);
+      }
+      term = frame.get_term(heap)?;
+      is_isolated = frame.is_isolated;
+    }
+    return heap.to_source(term);
+  }
+
+  /// Suspends the current frame to reduce the body of a block.
+  fn open_frame(&mut self, body: Gc) {
+    let frame = std::mem::replace(&mut self.frame, Frame::new(body));
+    self.frames.push(frame);
+  }
+
+  /// Suspends the current frame to run the body of a block against an
+  /// empty stack, as `l` does.
+  fn open_isolated_frame(&mut self, body: Gc) {
+    self.open_frame(body);
+    self.frame.is_isolated = true;
+  }
+
+  /// Wraps what is left of the current frame in a block, and pushes
+  /// it onto the frame that was suspended to reduce it.
+  fn close_frame(&mut self, heap: &mut Heap) -> Result<()> {
+    if self.frame.is_isolated {
+      return self.close_isolated_frame(heap);
+    }
+    let body = self.frame.get_term(heap)?;
+    let target = heap.new_block(body)?;
+    self.frame = self.frames.pop().ok_or(Error::Bug)?;
+    self.push_environment(target);
+    return Ok(());
+  }
+
+  /// Ends a frame opened by `l`. If it ran to the end without getting
+  /// stuck, its stack is pushed onto the suspended frame's stack.
+  /// Otherwise what is left of it goes back in a block ahead of the
+  /// `l`: in the continuation if it was cut short, or in the output
+  /// if it got stuck, since its stuck code can't see the stack beneath
+  /// it.
+  fn close_isolated_frame(&mut self, heap: &mut Heap) -> Result<()> {
+    if self.frame.con.is_empty() && self.frame.err.is_empty() {
+      let frame = self.frames.pop().ok_or(Error::Bug)?;
+      let frame = std::mem::replace(&mut self.frame, frame);
+      self.frame.env.extend(frame.env);
+      return Ok(());
+    }
+    let body = self.frame.get_term(heap)?;
+    let target = heap.new_block(body)?;
+    let code = heap.new_opcode(Opcode::Isolate)?;
+    let frame = self.frames.pop().ok_or(Error::Bug)?;
+    let frame = std::mem::replace(&mut self.frame, frame);
+    self.push_environment(target);
+    if frame.con.is_empty() {
+      self.thunk(code);
+    } else {
+      self.push_continuation_front(code);
+    }
+    return Ok(());
+  }
+
+  /// Deletes every node unreachable from the library or this thread.
+  fn collect(&self, heap: &mut Heap, tab: &Library) -> Result<usize> {
+    for pointer in tab.values() {
+      heap.mark(*pointer)?;
+    }
+    self.frame.mark(heap)?;
+    for frame in self.frames.iter() {
+      frame.mark(heap)?;
+    }
+    for (body, term) in self.forced.iter() {
+      heap.mark(*body)?;
+      heap.mark(*term)?;
+    }
+    heap.mark_roots()?;
+    return heap.sweep();
+  }
+
+  fn push_continuation_front(&mut self, data: Gc) {
+    self.frame.con.push_front(data);
+  }
+
+  #[allow(dead_code)]
+  fn push_continuation_back(&mut self, data: Gc) {
+    self.frame.con.push_back(data);
+  }
+
+  fn pop_continuation(
+    &mut self, heap: &mut Heap) -> Result<Gc> {
+    let mut space_quota = self.config.continuation_quota;
+    loop {
+      let code = self.frame.con.pop_front().ok_or(Error::Bug)?;
+      if heap.is_sequence(code)? {
+        if space_quota == 0 {
+          self.frame.con.push_front(code);
+          return Err(Error::Space);
+        }
+        space_quota -= 1;
+        let fst = heap.get_sequence_fst(code)?;
+        let snd = heap.get_sequence_snd(code)?;
+        self.frame.con.push_front(snd);
+        self.frame.con.push_front(fst);
+      } else {
+        return Ok(code);
+      }
+    }
+  }
+
+  fn is_monadic(&self) -> bool {
+    return self.env_len() >= 1;
+  }
+
+  fn is_dyadic(&self) -> bool {
+    return self.env_len() >= 2;
+  }
+
+  fn is_triadic(&self) -> bool {
+    return self.env_len() >= 3;
+  }
+
+  fn push_environment(&mut self, data: Gc) {
+    self.frame.env.push(data);
+  }
+
+  fn pop_environment(&mut self) -> Result<Gc> {
+    return self.frame.env.pop().ok_or(Error::Underflow {
+      needed: 1,
+      found: 0,
+    });
+  }
+
+  fn peek_environment(&mut self) -> Result<Gc> {
+    return self.frame.env.last().copied().ok_or(Error::Underflow {
+      needed: 1,
+      found: 0,
+    });
+  }
+
+  /// Moves the environment and the given code into the output,
+  /// recording enough to explain later why the code was stuck.
+  ///
+  /// Reduction then carries on with the rest of the continuation
+  /// against an empty environment, so code that isn't stuck still
+  /// reduces. The output is each stuck fragment in the order it was
+  /// met, with the environment it was met in ahead of it, followed by
+  /// whatever is left in the environment at the end.
+  fn thunk(&mut self, root: Gc) {
+    let found = self.env_len();
+    self.frame.err.append(&mut self.frame.env);
+    self.thunks.push(Thunk {
+      code: root,
+      position: self.err_len(),
+      found: found,
+    });
+    self.frame.err.push(root);
+  }
+
+  /// Handles an opcode that found too few blocks on the stack.
+  fn underflow(&mut self, code: Gc, heap: &Heap) -> Result<()> {
+    if self.config.strict_arity {
+      let opcode = heap.get_opcode(code)?;
+      return Err(Error::Underflow {
+        needed: opcode.get_arity().unwrap_or(0),
+        found: self.env_len(),
+      });
+    }
+    self.thunk(code);
+    return Ok(());
+  }
+
+  fn step(
+    &mut self,
+    heap: &mut Heap,
+    tab: &HashMap<Rc<str>, Gc>) -> Result<()> {
+    self.cost = 1;
+    if self.frame.con.is_empty() {
+      self.close_frame(heap)?;
+      return self.record("]".to_string());
+    }
+    let code = self.pop_continuation(heap)?;
+    let result = self.dispatch(code, heap, tab);
+    if let Err(Error::Space) = result {
+      // Allocating opcodes leave the frame untouched when they fail,
+      // so restoring the code lets the step be retried.
+      self.push_continuation_front(code);
+    }
+    result?;
+    if self.trace.is_some() {
+      return self.record(heap.to_source(code)?);
+    }
+    return Ok(());
+  }
+
+  /// Adds a step to the trace, if the thread is recording one.
+  fn record(&mut self, code: String) -> Result<()> {
+    let depth = self.frame.env.len();
+    if let Some(trace) = self.trace.as_mut() {
+      trace.push(TraceEvent {
+        code: code,
+        depth: depth,
+      });
+    }
+    return Ok(());
+  }
+
+  fn dispatch(
+    &mut self,
+    code: Gc,
+    heap: &mut Heap,
+    tab: &Library) -> Result<()> {
+    if heap.is_block(code)? {
+      if self.config.check_arity && !heap.check_arity(code, tab)? {
+        let position = self.err_len() + self.env_len();
+        return Err(Error::Arity {
+          position: position,
+        });
+      }
+      if self.config.strategy == Strategy::Innermost {
+        let body = heap.get_block_body(code)?;
+        self.open_frame(body);
+      } else {
+        self.push_environment(code);
+      }
+    } else if heap.is_opcode(code)? {
+      return self.exec(code, heap, tab);
+    } else if heap.is_word(code)? {
+      let code_value = heap.get_word(code)?;
+      match tab.get(&code_value) {
+        Some(binding) => {
+          if heap.is_opcode(*binding)? {
+            return self.exec(*binding, heap, tab);
+          }
+          if let Some(quota) = self.config.expansion_quota {
+            if self.expansions >= quota {
+              return Err(Error::Expansion);
+            }
+          }
+          self.expansions += 1;
+          self.push_continuation_front(*binding);
+        }
+        None => {
+          if let Some(handler) = self.handler.as_mut() {
+            let mut stack = Stack {
+              heap: heap,
+              env: &mut self.frame.env,
+              config: &self.config,
+            };
+            if handler(&code_value, &mut stack)? {
+              return Ok(());
+            }
+          }
+          self.thunk(code);
+        }
+      }
+      return Ok(());
+    } else if heap.is_id(code)? || heap.is_hint(code)? {
+      return Ok(());
+    } else {
+      return Err(self.bug(code, heap));
+    }
+    return Ok(());
+  }
+
+  /// The error for finding `code` where it should never be, or a
+  /// panic if the config asks for one.
+  fn bug(&self, code: Gc, heap: &Heap) -> Error {
+    if self.config.debug_panic_on_bug {
+      let quoted = heap.to_source(code)
+        .unwrap_or_else(|error| format!("{:?}", error));
+      panic!("thread can't run `{}`", quoted);
+    }
+    return Error::Bug;
+  }
+
+  /// Reduces a block body on its own, or finds the result of having
+  /// done so before. Nothing is collected while it runs, so if the heap
+  /// fills up the whole step can be retried. Words it expands count
+  /// towards this thread's expansion quota. The body is reduced without
+  /// its surroundings, so arity checks are left for when the result is
+  /// run: a body that would underflow on its own needn't on the stack
+  /// it's applied to.
+  fn force(
+    &mut self,
+    body: Gc,
+    quota: u64,
+    heap: &mut Heap,
+    tab: &Library) -> Result<Gc> {
+    if let Some(term) = self.forced.get(&body) {
+      return Ok(*term);
+    }
+    let mut config = self.config;
+    config.call_by_need = None;
+    config.strict_arity = false;
+    config.check_arity = false;
+    let mut thread = Thread::with_continuation(body, &config);
+    thread.expansions = self.expansions;
+    let mut steps = 0;
+    while steps < quota && thread.has_continuation() {
+      thread.step(heap, tab)?;
+      steps += thread.cost;
+    }
+    self.cost = self.cost.saturating_add(steps);
+    self.expansions = thread.expansions;
+    if thread.has_continuation() {
+      return Ok(body);
+    }
+    let term = thread.get_term(heap)?;
+    self.forced.insert(body, term);
+    return Ok(term);
+  }
+
+  /// Executes an opcode against the environment.
+  fn exec(
+    &mut self, code: Gc, heap: &mut Heap, tab: &Library) -> Result<()> {
+    let opcode = heap.get_opcode(code)?;
+    if !self.config.allowed.contains(opcode.get_name()) {
+      return Err(Error::Forbidden);
+    }
+    self.cost = self.config.costs.get(opcode.get_name());
+    match opcode {
+      Opcode::App => {
+        if !self.is_monadic() {
+          return self.underflow(code, heap);
+        }
+        let source = self.peek_environment()?;
+        let mut target = heap.get_block_body(source)?;
+        if let Some(quota) = self.config.call_by_need {
+          target = self.force(target, quota, heap, tab)?;
+        }
+        self.pop_environment()?;
+        self.push_continuation_front(target);
+      }
+      Opcode::Box => {
+        if !self.is_monadic() {
+          return self.underflow(code, heap);
+        }
+        let source = self.peek_environment()?;
+        if heap.get_depth(source)? >= self.config.nesting_quota {
+          return Err(Error::Space);
+        }
+        let target = heap.new_block(source)?;
+        self.pop_environment()?;
+        self.push_environment(target);
+      }
+      Opcode::Cat => {
+        if !self.is_dyadic() {
+          return self.underflow(code, heap);
+        }
+        let depth = self.frame.env.len();
+        let rhs = self.frame.env[depth - 1];
+        let lhs = self.frame.env[depth - 2];
+        let rhs_body = heap.get_block_body(rhs)?;
+        let lhs_body = heap.get_block_body(lhs)?;
+        // Joining an empty block to another gives back the other one,
+        // so there's no need to build a copy of it.
+        let target = if heap.is_id(rhs_body)? {
+          lhs
+        } else if heap.is_id(lhs_body)? {
+          rhs
+        } else {
+          let target_body = heap.new_sequence(lhs_body, rhs_body)?;
+          heap.new_block(target_body)?
+        };
+        self.pop_environment()?;
+        self.pop_environment()?;
+        self.push_environment(target);
+      }
+      Opcode::Copy => {
+        if !self.is_monadic() {
+          return self.underflow(code, heap);
+        }
+        let source = self.peek_environment()?;
+        self.push_environment(source);
+      }
+      Opcode::Drop => {
+        if !self.is_monadic() {
+          return self.underflow(code, heap);
+        }
+        self.pop_environment()?;
+      }
+      Opcode::Swap => {
+        if !self.is_dyadic() {
+          return self.underflow(code, heap);
+        }
+        let fst = self.pop_environment()?;
+        let snd = self.pop_environment()?;
+        self.push_environment(fst);
+        self.push_environment(snd);
+      }
+      Opcode::Keep => {
+        if !self.is_dyadic() {
+          return self.underflow(code, heap);
+        }
+        let source = self.pop_environment()?;
+        let target = heap.get_block_body(source)?;
+        let kept = self.peek_environment()?;
+        self.push_continuation_front(kept);
+        self.push_continuation_front(target);
+      }
+      Opcode::Append => {
+        if !self.is_dyadic() {
+          return self.underflow(code, heap);
+        }
+        let depth = self.frame.env.len();
+        let rhs = self.frame.env[depth - 1];
+        let lhs = self.frame.env[depth - 2];
+        let nesting = heap.get_depth(lhs)?.max(heap.get_depth(rhs)?);
+        if nesting >= self.config.nesting_quota {
+          return Err(Error::Space);
+        }
+        let target_body = heap.new_sequence(lhs, rhs)?;
+        let target = heap.new_block(target_body)?;
+        self.pop_environment()?;
+        self.pop_environment()?;
+        self.push_environment(target);
+      }
+      Opcode::Isolate => {
+        if !self.is_monadic() {
+          return self.underflow(code, heap);
+        }
+        let source = self.pop_environment()?;
+        let target = heap.get_block_body(source)?;
+        self.open_isolated_frame(target);
+      }
+      Opcode::Rot => {
+        if !self.is_triadic() {
+          return self.underflow(code, heap);
+        }
+        let trd = self.pop_environment()?;
+        let snd = self.pop_environment()?;
+        let fst = self.pop_environment()?;
+        self.push_environment(snd);
+        self.push_environment(trd);
+        self.push_environment(fst);
+      }
+      Opcode::Prop | Opcode::Forall => {
+        self.thunk(code);
+        return Ok(());
+      }
+    }
+    return Ok(());
+  }
+}
+
+/// Evaluates each line of arbitrary input in a fresh pod, returning
+/// the outputs joined by newlines. Whatever the input, this returns
+/// rather than panicking: pointers are bounds-checked, traversals are
+/// iterative, and every allocation is limited by `space_quota`.
+pub fn try_eval(
+  src: &str,
+  space_quota: usize,
+  time_quota: u64) -> Result<String> {
+  let heap = Heap::with_capacity(space_quota);
+  let mut pod = Pod::with_heap(heap, time_quota);
+  let mut dst = String::new();
+  for (index, line) in src.lines().enumerate() {
+    if index > 0 {
+      dst.push('\n');
+    }
+    dst.push_str(&pod.eval(line, time_quota)?);
+  }
+  return Ok(dst);
+}
+
+/// An abstract value, as seen by the stack-effect analyzer.
+#[derive(Clone)]
+enum Value {
+  /// A block literal with the given body.
+  Block(Gc),
+  /// A value that was boxed.
+  Boxed(Rc<Value>),
+  /// Two values that were concatenated.
+  Cat(Rc<Value>, Rc<Value>),
+  /// An unknown value taken from beneath the term being analyzed.
+  Input,
+}
+
+/// A pending piece of work for the stack-effect analyzer.
+enum Work {
+  Code(Gc),
+  Apply(Value),
+  Push(Value),
+  /// Put back the stack that `l` set aside, beneath what is on the
+  /// stack now.
+  Restore(Vec<Value>),
+}
+
+/// Abstractly interprets a term, tracking the shape of the stack
+/// rather than running the term against real data.
+struct Analyzer<'a> {
+  heap: &'a Heap,
+  tab: &'a Library,
+  stack: Vec<Value>,
+  inputs: usize,
+  copies: usize,
+  drops: usize,
+  fuel: usize,
+  /// The number of `l`s whose blocks are being run.
+  isolation: usize,
+  /// Whether a block run by `l` took more values than it was given.
+  is_stuck: bool,
+}
+
+impl<'a> Analyzer<'a> {
+  fn new(heap: &'a Heap, tab: &'a Library) -> Self {
+    Analyzer {
+      heap: heap,
+      tab: tab,
+      stack: Vec::new(),
+      inputs: 0,
+      copies: 0,
+      drops: 0,
+      fuel: 1 << 16,
+      isolation: 0,
+      is_stuck: false,
+    }
+  }
+
+  fn pop(&mut self) -> Value {
+    match self.stack.pop() {
+      Some(value) => {
+        return value;
+      }
+      None => {
+        if self.isolation > 0 {
+          self.is_stuck = true;
+        } else {
+          self.inputs += 1;
+        }
+        return Value::Input;
+      }
+    }
+  }
+
+  /// Runs a term, returning false if its effect depends on something
+  /// the analyzer can't see: an input applied as a block, an undefined
+  /// word, an inert opcode, a block that gets stuck under `l`, or more
+  /// work than the analyzer will do.
+  fn run(&mut self, root: Gc) -> Result<bool> {
+    let mut con = vec![Work::Code(root)];
+    while let Some(work) = con.pop() {
+      if self.fuel == 0 || self.is_stuck {
+        return Ok(false);
+      }
+      self.fuel -= 1;
+      let code = match work {
+        Work::Code(code) => {
+          code
+        }
+        Work::Apply(Value::Block(body)) => {
+          con.push(Work::Code(body));
+          continue;
+        }
+        Work::Apply(Value::Boxed(value)) => {
+          self.stack.push((*value).clone());
+          continue;
+        }
+        Work::Apply(Value::Cat(lhs, rhs)) => {
+          con.push(Work::Apply((*rhs).clone()));
+          con.push(Work::Apply((*lhs).clone()));
+          continue;
+        }
+        Work::Apply(Value::Input) => {
+          return Ok(false);
+        }
+        Work::Push(value) => {
+          self.stack.push(value);
+          continue;
+        }
+        Work::Restore(saved) => {
+          let results = std::mem::replace(&mut self.stack, saved);
+          self.stack.extend(results);
+          self.isolation -= 1;
+          continue;
+        }
+      };
+      match self.heap.get_ref(code)? {
+        &Object::Id | &Object::Hint(_) => {
+          //
+        }
+        &Object::Block(body) => {
+          self.stack.push(Value::Block(body));
+        }
+        &Object::Sequence(fst, snd) => {
+          con.push(Work::Code(snd));
+          con.push(Work::Code(fst));
+        }
+        &Object::Word(ref value) => {
+          match self.tab.get(value) {
+            Some(binding) => {
+              con.push(Work::Code(*binding));
+            }
+            None => {
+              return Ok(false);
+            }
+          }
+        }
+        &Object::Opcode(opcode) => {
+          match opcode {
+            Opcode::App => {
+              let value = self.pop();
+              con.push(Work::Apply(value));
+            }
+            Opcode::Box => {
+              let value = self.pop();
+              self.stack.push(Value::Boxed(Rc::new(value)));
+            }
+            Opcode::Cat => {
+              let rhs = self.pop();
+              let lhs = self.pop();
+              self.stack.push(Value::Cat(Rc::new(lhs), Rc::new(rhs)));
+            }
+            Opcode::Copy => {
+              let value = self.pop();
+              self.copies += 1;
+              self.stack.push(value.clone());
+              self.stack.push(value);
+            }
+            Opcode::Drop => {
+              self.pop();
+              self.drops += 1;
+            }
+            Opcode::Swap => {
+              let fst = self.pop();
+              let snd = self.pop();
+              self.stack.push(fst);
+              self.stack.push(snd);
+            }
+            Opcode::Keep => {
+              let source = self.pop();
+              let kept = self.pop();
+              self.copies += 1;
+              self.stack.push(kept.clone());
+              con.push(Work::Push(kept));
+              con.push(Work::Apply(source));
+            }
+            Opcode::Append => {
+              let rhs = self.pop();
+              let lhs = self.pop();
+              let lhs = Value::Boxed(Rc::new(lhs));
+              let rhs = Value::Boxed(Rc::new(rhs));
+              self.stack.push(Value::Cat(Rc::new(lhs), Rc::new(rhs)));
+            }
+            Opcode::Rot => {
+              let trd = self.pop();
+              let snd = self.pop();
+              let fst = self.pop();
+              self.stack.push(snd);
+              self.stack.push(trd);
+              self.stack.push(fst);
+            }
+            Opcode::Isolate => {
+              let value = self.pop();
+              let saved = std::mem::take(&mut self.stack);
+              self.isolation += 1;
+              con.push(Work::Restore(saved));
+              con.push(Work::Apply(value));
+            }
+            Opcode::Prop | Opcode::Forall => {
+              return Ok(false);
+            }
+          }
+        }
+      }
+    }
+    return Ok(true);
+  }
+}
+
+impl Heap {
+  /// Verifies that a term uses every value exactly once: it never
+  /// copies or drops anything, however its blocks are applied. Terms
+  /// whose behavior can't be determined statically are rejected too.
+  fn check_linear(&self, root: Gc, tab: &Library) -> Result<()> {
+    let mut analyzer = Analyzer::new(self, tab);
+    if !analyzer.run(root)? {
+      return Err(Error::Linear);
+    }
+    if analyzer.copies > 0 || analyzer.drops > 0 {
+      return Err(Error::Linear);
+    }
+    return Ok(());
+  }
+
+  /// Infers the number of values a term consumes and produces, or
+  /// `None` if its effect can't be determined statically.
+  fn stack_effect(
+    &self, root: Gc, tab: &Library) -> Result<Option<(usize, usize)>> {
+    let mut analyzer = Analyzer::new(self, tab);
+    if !analyzer.run(root)? {
+      return Ok(None);
+    }
+    return Ok(Some((analyzer.inputs, analyzer.stack.len())));
+  }
+
+  /// Whether applying a block to a stack of `inputs` values could take
+  /// more values than there are. Blocks whose effect can't be
+  /// determined statically, such as those that apply an input, may
+  /// underflow as far as this can tell.
+  fn may_underflow(
+    &self, block: Gc, inputs: usize, tab: &Library) -> Result<bool> {
+    let body = self.get_block_body(block)?;
+    let mut analyzer = Analyzer::new(self, tab);
+    if !analyzer.run(body)? {
+      return Ok(true);
+    }
+    return Ok(analyzer.inputs > inputs);
+  }
+
+  /// Whether reducing a term would leave it as it is, without running
+  /// anything: it has no opcode with the blocks it needs in front of
+  /// it, no word the library defines, and no hint, since hints are
+  /// dropped. See `normal_thunks`.
+  #[allow(dead_code)]
+  fn is_normal(
+    &self, root: Gc, tab: &Library, config: &Config) -> Result<bool> {
+    return Ok(self.normal_thunks(root, tab, config)?.is_some());
+  }
+
+  /// If a term is in normal form, the code in it that reducing it
+  /// would find stuck, recorded as a thread would record it. Otherwise
+  /// `None`. Terms that reducing could fail on, or that would be
+  /// reduced inside their blocks under the innermost strategy, are
+  /// never taken to be normal.
+  fn normal_thunks(
+    &self,
+    root: Gc,
+    tab: &Library,
+    config: &Config) -> Result<Option<Vec<Thunk>>> {
+    if config.strategy == Strategy::Innermost || config.check_arity {
+      return Ok(None);
+    }
+    let mut thunks = Vec::new();
+    let mut env_len = 0;
+    let mut err_len = 0;
+    let mut elements = self.get_elements(root)?;
+    elements.reverse();
+    while let Some(element) = elements.pop() {
+      match self.get_ref(element)? {
+        &Object::Id => {
+          continue;
+        }
+        &Object::Hint(_) => {
+          return Ok(None);
+        }
+        &Object::Block(_) => {
+          env_len += 1;
+          continue;
+        }
+        &Object::Sequence(_, _) => {
+          let mut inner = self.get_elements(element)?;
+          inner.reverse();
+          elements.append(&mut inner);
+          continue;
+        }
+        &Object::Opcode(opcode) => {
+          if !config.allowed.contains(opcode.get_name()) {
+            return Ok(None);
+          }
+          if let Some(arity) = opcode.get_arity() {
+            if env_len >= arity || config.strict_arity {
+              return Ok(None);
+            }
+          }
+        }
+        &Object::Word(ref value) => {
+          if tab.contains_key(value) {
+            return Ok(None);
+          }
+        }
+      }
+      err_len += env_len;
+      thunks.push(Thunk {
+        code: element,
+        position: err_len,
+        found: env_len,
+      });
+      err_len += 1;
+      env_len = 0;
+    }
+    return Ok(Some(thunks));
+  }
+
+  /// Checks a block against its stack-effect annotation: a hint such
+  /// as `(2-1)` at the start of its body, meaning that the block
+  /// consumes two values and produces one. Blocks without an
+  /// annotation pass, as do blocks whose effect can't be determined
+  /// statically.
+  fn check_arity(&self, block: Gc, tab: &Library) -> Result<bool> {
+    let body = self.get_block_body(block)?;
+    let annotation = match self.get_elements(body)?.first() {
+      Some(&code) if self.is_hint(code)? => {
+        self.get_hint(code)?
+      }
+      _ => {
+        return Ok(true);
+      }
+    };
+    let data = match ARITY_REGEX.captures(&annotation) {
+      Some(data) => {
+        data
+      }
+      None => {
+        return Ok(true);
+      }
+    };
+    let inputs: usize = data[1].parse().or(Err(Error::Syntax))?;
+    let outputs: usize = data[2].parse().or(Err(Error::Syntax))?;
+    let mut analyzer = Analyzer::new(self, tab);
+    if !analyzer.run(body)? {
+      return Ok(true);
+    }
+    return Ok(analyzer.inputs == inputs && analyzer.stack.len() == outputs);
+  }
+}
+
+/// A range of bytes in some source text.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+/// The role a token plays in source text.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TokenClass {
+  /// The `:word` or `~word` that begins an insert or delete.
+  Key,
+  /// The quoted documentation in an insert.
+  Doc,
+  Bracket,
+  Opcode,
+  Hint,
+  DefinedWord,
+  UndefinedWord,
+  /// A single lowercase letter that isn't an opcode.
+  Invalid,
+}
+
+/// A handle to a term pinned in a pod.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PinId {
+  index: usize,
+}
+
+/// Why a reduction stopped.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Outcome {
+  /// Every instruction ran to completion.
+  Completed,
+  /// The time quota ran out with instructions left to run.
+  QuotaExhausted,
+  /// Every instruction ran, but some could not make progress and
+  /// remain in the output; `first_stuck` is the earliest of them.
+  Stuck { first_stuck: String },
+}
+
+/// A summary of the work done by a single evaluation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EvalReport {
+  pub output: String,
+  pub outcome: Outcome,
+  pub steps_used: u64,
+  pub nodes_allocated: u64,
+  pub nodes_collected: usize,
+  pub peak_live: usize,
+  /// Whether the output was remembered from an earlier reduction of
+  /// the same term, rather than reduced again. See `cache_quota`.
+  pub is_cached: bool,
+}
+
+pub struct Pod {
+  heap: Heap,
+  tab: Library,
+  /// The library's keys, in the order they were first defined.
+  order: Vec<Rc<str>>,
+  docs: HashMap<Rc<str>, Rc<str>>,
+  config: Config,
+  time_quota: u64,
+  handler: Option<Box<Handler>>,
+  low_fuel: Option<(u64, Box<LowFuel>)>,
+  /// Remembered reductions, by the structural hash of their source.
+  cache: HashMap<u64, Memo>,
+  clock: u64,
+}
+
+/// A term's reduced form, remembered by a pod.
+#[derive(Clone)]
+struct Memo {
+  /// The term that was reduced, to tell it apart from another term
+  /// with the same hash.
+  source: Gc,
+  term: Gc,
+  outcome: Outcome,
+  /// The steps the reduction took. A memo only answers for a quota
+  /// that would have allowed as many.
+  steps: u64,
+  /// When the memo was last used, by the pod's clock.
+  used: u64,
+}
+
+impl Pod {
+  fn with_heap(heap: Heap, time_quota: u64) -> Self {
+    Pod {
+      heap: heap,
+      tab: HashMap::new(),
+      order: Vec::new(),
+      docs: HashMap::new(),
+      config: Config::default(),
+      time_quota: time_quota,
+      handler: None,
+      low_fuel: None,
+      cache: HashMap::new(),
+      clock: 0,
+    }
+  }
+
+  /// Loads a pod from source text, one record at a time. If a record
+  /// fails, returns the number of the line it starts on, counting from
+  /// 1, along with its error.
+  pub fn from_string(
+    src: &str,
+    space_quota: usize,
+    time_quota: u64) -> std::result::Result<Self, (usize, Error)> {
+    let heap = Heap::with_capacity(space_quota);
+    let mut pod = Pod::with_heap(heap, time_quota);
+    for (line, record) in numbered_records(src) {
+      pod.eval(&record, time_quota).map_err(|error| (line, error))?;
+    }
+    return Ok(pod);
+  }
+
+  /// Loads a pod like `from_string`, but carries on past records that
+  /// fail, so that every mistake in a source can be found at once.
+  /// Returns the pod built from the records that succeeded, and each
+  /// failure with the number of the line its record starts on. A fatal
+  /// error still stops the load, since the pod can't be trusted after
+  /// it.
+  pub fn build(
+    src: &str,
+    space_quota: usize,
+    time_quota: u64) -> (Self, Vec<(usize, Error)>) {
+    let heap = Heap::with_capacity(space_quota);
+    let mut pod = Pod::with_heap(heap, time_quota);
+    let mut errors = Vec::new();
+    for (line, record) in numbered_records(src) {
+      if let Err(error) = pod.eval(&record, time_quota) {
+        errors.push((line, error));
+        if error.is_fatal() {
+          break;
+        }
+      }
+    }
+    return (pod, errors);
+  }
+
+  /// Loads a pod like `from_string`, but collects garbage once at the
+  /// end rather than after every record, which makes large sources
+  /// load faster. If the heap fills up part way through, garbage is
+  /// collected early and the record retried.
+  pub fn from_string_deferred(
+    src: &str,
+    space_quota: usize,
+    time_quota: u64) -> Result<Self> {
+    let heap = Heap::with_capacity(space_quota);
+    let mut pod = Pod::with_heap(heap, time_quota);
+    for record in records(src) {
+      match pod.eval_no_gc(&record, time_quota) {
+        Err(Error::Space) => {
+          pod.collect()?;
+          pod.eval_no_gc(&record, time_quota)?;
+        }
+        result => {
+          result?;
+        }
+      }
+    }
+    pod.collect()?;
+    return Ok(pod);
+  }
+
+  /// Loads a pod from a directory written by `export_split`, with one
+  /// `.md` file for each definition. The definitions are made in an
+  /// order where each comes after the ones it mentions.
+  pub fn import_split(
+    dir: &std::path::Path,
+    space_quota: usize,
+    time_quota: u64) -> Result<Self> {
+    let mut sources = HashMap::new();
+    for entry in std::fs::read_dir(dir).or(Err(Error::Io))? {
+      let path = entry.or(Err(Error::Io))?.path();
+      if path.extension() != Some(std::ffi::OsStr::new("md")) {
+        continue;
+      }
+      let key = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(key) => {
+          key.to_string()
+        }
+        None => {
+          continue;
+        }
+      };
+      let src = std::fs::read_to_string(&path).or(Err(Error::Io))?;
+      sources.insert(key, src);
+    }
+    let heap = Heap::with_capacity(space_quota);
+    let mut pod = Pod::with_heap(heap, time_quota);
+    for key in dependency_order(&sources) {
+      for record in records(&sources[&key]) {
+        pod.eval(&record, time_quota)?;
+      }
+    }
+    return Ok(pod);
+  }
+
+  /// Loads the default pod. If `SUNDIAL_HOME` is set, it's read from
+  /// `$SUNDIAL_HOME/pod/default.md`, failing with `Error::Home` if that
+  /// can't be read. Otherwise the prelude built into the crate is used.
+  pub fn default(space_quota: usize, time_quota: u64) -> Result<Self> {
+    let home = std::env::var_os("SUNDIAL_HOME");
+    let home = home.as_ref().map(std::path::Path::new);
+    return Pod::from_home(home, space_quota, time_quota);
+  }
+
+  /// Loads the default pod from `home` as `default` would from
+  /// `SUNDIAL_HOME`, or the built-in prelude if there is no home.
+  fn from_home(
+    home: Option<&std::path::Path>,
+    space_quota: usize,
+    time_quota: u64) -> Result<Self> {
+    let src = match home {
+      Some(home) => {
+        let path = home.join("pod").join("default.md");
+        std::fs::read_to_string(path).or(Err(Error::Home))?
+      }
+      None => {
+        PRELUDE.to_string()
+      }
+    };
+    let pod = Pod::from_string(&src, space_quota, time_quota);
+    return pod.map_err(|(_, error)| error);
+  }
+
+  pub fn eval(&mut self, src: &str, time_quota: u64) -> Result<String> {
+    let report = self.run(src, time_quota)?;
+    self.collect()?;
+    return Ok(report.output);
+  }
+
+  /// Evaluates each statement of a program in turn, returning their
+  /// outputs joined by newlines. Statements are separated by `;` or by
+  /// line breaks, so `:foo [A]; foo` defines `foo` and then uses it.
+  pub fn eval_program(
+    &mut self, src: &str, time_quota: u64) -> Result<String> {
+    let mut dst = String::new();
+    for (index, statement) in statements(src).iter().enumerate() {
+      if index > 0 {
+        dst.push('\n');
+      }
+      dst.push_str(&self.eval(statement.trim(), time_quota)?);
+    }
+    return Ok(dst);
+  }
+
+  /// Evaluates source text that may span several lines, as if it were
+  /// written on one. Every run of whitespace, including tabs and line
+  /// breaks, is read as a single space.
+  pub fn eval_multiline(
+    &mut self, src: &str, time_quota: u64) -> Result<String> {
+    let line: Vec<&str> = src.split_whitespace().collect();
+    return self.eval(&line.join(" "), time_quota);
+  }
+
+  /// Evaluates a line like `eval`, with extra definitions in place for
+  /// this call only. Each definition is reduced in order, as if it
+  /// were inserted, and the library is restored afterwards.
+  pub fn eval_with(
+    &mut self,
+    overlay: &[(&str, &str)],
+    src: &str,
+    time_quota: u64) -> Result<String> {
+    let tab = self.tab.clone();
+    let order = self.order.clone();
+    let docs = self.docs.clone();
+    let mut result = Ok(());
+    for (name, body) in overlay.iter() {
+      if !NAME_REGEX.is_match(name) {
+        result = Err(Error::Syntax);
+        break;
+      }
+      if let Err(error) = self.insert((*name).into(), body, time_quota) {
+        result = Err(error);
+        break;
+      }
+    }
+    let report = result.and_then(|_| self.run(src, time_quota));
+    self.tab = tab;
+    self.order = order;
+    self.docs = docs;
+    self.cache.clear();
+    self.collect()?;
+    return Ok(report?.output);
+  }
+
+  /// The words defined in the library, in order.
+  pub fn words(&self) -> Vec<String> {
+    let mut words: Vec<String> = self.tab.keys()
+      .map(|key| key.to_string()).collect();
+    words.sort();
+    return words;
+  }
+
+  /// Evaluates a line like `eval`, but returns `None` if the line is
+  /// blank, so that it can be told apart from a term that reduces to
+  /// nothing.
+  pub fn eval_opt(
+    &mut self, src: &str, time_quota: u64) -> Result<Option<String>> {
+    if src.trim().is_empty() {
+      return Ok(None);
+    }
+    return self.eval(src, time_quota).map(Some);
+  }
+
+  /// Evaluates a line like `eval`, but leaves garbage on the heap
+  /// until the next `collect`. Batches of small evaluations can use
+  /// this to pay for a single collection at the end.
+  pub fn eval_no_gc(
+    &mut self, src: &str, time_quota: u64) -> Result<String> {
+    let report = self.run(src, time_quota)?;
+    return Ok(report.output);
+  }
+
+  /// Evaluates a line like `eval`, reporting the steps taken and the
+  /// heap traffic it caused.
+  pub fn eval_instrumented(
+    &mut self, src: &str, time_quota: u64) -> Result<EvalReport> {
+    let allocated = self.heap.allocated;
+    self.heap.peak_live = self.heap.live;
+    let mut report = self.run(src, time_quota)?;
+    report.nodes_allocated = self.heap.allocated - allocated;
+    report.peak_live = self.heap.peak_live;
+    report.nodes_collected = self.collect()?;
+    return Ok(report);
+  }
+
+  /// Evaluates a line without collecting garbage. The report's heap
+  /// statistics are left for the caller to fill in. On a monotonic
+  /// heap, a line that fails gives back the slots it allocated, since
+  /// they wouldn't be reused otherwise.
+  fn run(&mut self, src: &str, time_quota: u64) -> Result<EvalReport> {
+    let checkpoint = self.heap.checkpoint();
+    let report = self.run_unguarded(src, time_quota);
+    if report.is_err() && self.heap.is_monotonic {
+      self.heap.rollback(checkpoint)?;
+    }
+    return report;
+  }
+
+  fn run_unguarded(
+    &mut self, src: &str, time_quota: u64) -> Result<EvalReport> {
+    let mut dst = String::new();
+    let mut steps = 0;
+    let mut outcome = Outcome::Completed;
+    let mut is_cached = false;
+    if let Some(data) = POD_INSERT_REGEX.captures(src) {
+      let key: Rc<str> = data.get(1).expect("key").as_str().into();
+      let value_src = data.get(3).expect("value").as_str();
+      let reduction = self.insert(key.clone(), value_src, time_quota)?;
+      match data.get(2) {
+        Some(doc) => {
+          self.docs.insert(key.clone(), doc.as_str().into());
+        }
+        None => {
+          self.docs.remove(&key);
+        }
+      }
+      steps = reduction.steps;
+      outcome = self.get_outcome(&reduction)?;
+      self.push_key(&key, &mut dst);
+      quote(reduction.term, &self.heap, &mut dst)?;
+    } else if let Some(data) = POD_DELETE_REGEX.captures(src) {
+      let key: Rc<str> = data.get(1).expect("key").as_str().into();
+      self.forget(&key);
+      dst.push('~');
+      dst.push_str(&key);
+    } else {
+      let source = parse(src, &mut self.heap, &self.config)?;
+      let key = self.get_memo_key(source)?;
+      self.clock += 1;
+      let clock = self.clock;
+      let is_hit = match key.and_then(|key| self.cache.get(&key)) {
+        Some(memo) if memo.steps <= time_quota => {
+          self.heap.diff(memo.source, source)?.is_none()
+        }
+        _ => {
+          false
+        }
+      };
+      let memo = key
+        .filter(|_| is_hit)
+        .and_then(|key| self.cache.get_mut(&key));
+      if let Some(memo) = memo {
+        memo.used = clock;
+        steps = memo.steps;
+        outcome = memo.outcome.clone();
+        is_cached = true;
+        quote(memo.term, &self.heap, &mut dst)?;
+      } else {
+        let reduction = self.reduce_hooked(source, time_quota)?;
+        steps = reduction.steps;
+        outcome = self.get_outcome(&reduction)?;
+        quote(reduction.term, &self.heap, &mut dst)?;
+        if let Some(key) = key {
+          if outcome != Outcome::QuotaExhausted {
+            let memo = Memo {
+              source: source,
+              term: reduction.term,
+              outcome: outcome.clone(),
+              steps: steps,
+              used: clock,
+            };
+            self.remember(key, memo);
+          }
+        }
+      }
+    }
+    return Ok(EvalReport {
+      output: dst,
+      outcome: outcome,
+      steps_used: steps,
+      nodes_allocated: 0,
+      nodes_collected: 0,
+      peak_live: 0,
+      is_cached: is_cached,
+    });
+  }
+
+  /// The key a term's reduced form is remembered under, or `None` if
+  /// it shouldn't be remembered: the cache is off, or a hook could make
+  /// the same term reduce differently next time.
+  fn get_memo_key(&self, source: Gc) -> Result<Option<u64>> {
+    if self.config.cache_quota == 0 {
+      return Ok(None);
+    }
+    if self.handler.is_some() || self.low_fuel.is_some() {
+      return Ok(None);
+    }
+    return Ok(Some(self.heap.hash_term(source)?));
+  }
+
+  /// Remembers a reduced term, forgetting the least recently used one
+  /// if the cache is full.
+  fn remember(&mut self, key: u64, memo: Memo) {
+    while self.cache.len() >= self.config.cache_quota {
+      let oldest = self.cache.iter()
+        .min_by_key(|(_, memo)| memo.used)
+        .map(|(key, _)| *key);
+      match oldest {
+        Some(oldest) => {
+          self.cache.remove(&oldest);
+        }
+        None => {
+          break;
+        }
+      }
+    }
+    self.cache.insert(key, memo);
+  }
+
+  fn get_outcome(&self, reduction: &Reduction) -> Result<Outcome> {
+    if reduction.is_exhausted {
+      return Ok(Outcome::QuotaExhausted);
+    }
+    if let Some(thunk) = reduction.thunks.first() {
+      return Ok(Outcome::Stuck {
+        first_stuck: self.heap.to_source(thunk.code)?,
+      });
+    }
+    return Ok(Outcome::Completed);
+  }
+
+  /// Parses and reduces `src`, binding the result to `key`. Fails with
+  /// `Error::Shadow` if `key` would be read as an opcode.
+  fn insert(
+    &mut self,
+    key: Rc<str>,
+    src: &str,
+    time_quota: u64) -> Result<Reduction> {
+    if is_reserved(&key, &self.config) {
+      return Err(Error::Shadow);
+    }
+    self.cache.clear();
+    let value = parse(src, &mut self.heap, &self.config)?;
+    let thunks = self.heap.normal_thunks(value, &self.tab, &self.config)?;
+    let reduction = match thunks {
+      Some(thunks) => {
+        // Reducing a term in normal form gives it back, so there's no
+        // need to spend steps on it.
+        Reduction {
+          term: value,
+          stuck: None,
+          steps: 0,
+          thunks: thunks,
+          is_exhausted: false,
+          trace: vec![],
+          snapshots: vec![],
+        }
+      }
+      None => {
+        reduce(value, &mut self.heap, &self.tab, &self.config, time_quota)?
+      }
+    };
+    let term = match self.heap.compact_term(reduction.term) {
+      Err(Error::Space) => {
+        reduction.term
+      }
+      result => {
+        result?
+      }
+    };
+    let previous = self.tab.insert(key.clone(), term);
+    if let Some(probe_quota) = self.config.probe_quota {
+      if let Some(previous) = previous {
+        self.heap.push_root(previous);
+      }
+      let probe = reduce(
+        term, &mut self.heap, &self.tab, &self.config, probe_quota);
+      if previous.is_some() {
+        self.heap.pop_root()?;
+      }
+      if probe?.is_exhausted {
+        match previous {
+          Some(previous) => {
+            self.tab.insert(key, previous);
+          }
+          None => {
+            self.tab.remove(&key);
+          }
+        }
+        return Err(Error::Diverge);
+      }
+    }
+    if previous.is_none() {
+      self.order.push(key);
+    }
+    return Ok(reduction);
+  }
+
+  /// Removes the binding for `key` and its documentation.
+  fn forget(&mut self, key: &str) {
+    self.tab.remove(key);
+    self.docs.remove(key);
+    self.order.retain(|x| &**x != key);
+    self.cache.clear();
+  }
+
+  /// Copies this pod, so that changes to the copy don't affect the
+  /// original. The copy has the same library, documentation and
+  /// config, but none of the original's hooks.
+  pub fn fork(&self) -> Result<Pod> {
+    let mut pod = Pod {
+      heap: self.heap.clone(),
+      tab: self.tab.clone(),
+      order: self.order.clone(),
+      docs: self.docs.clone(),
+      config: self.config,
+      time_quota: self.time_quota,
+      handler: None,
+      low_fuel: None,
+      cache: self.cache.clone(),
+      clock: self.clock,
+    };
+    pod.collect()?;
+    return Ok(pod);
+  }
+
+  /// Registers a handler for undefined words met while evaluating a
+  /// term. Definitions are reduced without it, so a word only takes
+  /// effect when it is run.
+  pub fn on_undefined<F>(&mut self, handler: F)
+  where F: FnMut(&str, &mut Stack) -> Result<bool> + 'static {
+    self.handler = Some(Box::new(handler));
+  }
+
+  /// Registers a function to call when evaluating a term leaves only
+  /// `threshold` steps of its time quota, which can grant more steps
+  /// or stop the reduction early. After a grant it is called again
+  /// once only `threshold` steps are left, or once the grant is spent
+  /// if it left fewer than that.
+  pub fn on_low_fuel<F>(&mut self, threshold: u64, on_low_fuel: F)
+  where F: FnMut(u64) -> Fuel + 'static {
+    self.low_fuel = Some((threshold, Box::new(on_low_fuel)));
+  }
+
+  /// Reduces a term with this pod's hooks installed.
+  fn reduce_hooked(
+    &mut self, source: Gc, time_quota: u64) -> Result<Reduction> {
+    let mut thread = Thread::with_continuation(source, &self.config);
+    thread.handler = self.handler.as_deref_mut();
+    thread.low_fuel = self.low_fuel.as_mut()
+      .map(|(threshold, on_low_fuel)| (*threshold, on_low_fuel.as_mut()));
+    return run_thread(thread, &mut self.heap, &self.tab, time_quota);
+  }
+
+  /// The documentation given for `name` when it was defined, as in
+  /// `:name "documentation" body`.
+  pub fn doc(&self, name: &str) -> Option<String> {
+    return self.docs.get(name).map(|doc| doc.to_string());
+  }
+
+  /// Writes the start of a definition: the key, and its documentation
+  /// if it has any.
+  fn push_key(&self, key: &str, target: &mut String) {
+    target.push(':');
+    target.push_str(key);
+    target.push(' ');
+    if let Some(doc) = self.docs.get(key) {
+      target.push('"');
+      target.push_str(doc);
+      target.push_str("\" ");
+    }
+  }
+
+  /// Binds `name` to the reduced `body`, as the line `:name body`
+  /// would.
+  pub fn define(
+    &mut self, name: &str, body: &str, time_quota: u64) -> Result<()> {
+    if !NAME_REGEX.is_match(name) {
+      return Err(Error::Syntax);
+    }
+    self.insert(name.into(), body, time_quota)?;
+    self.collect()?;
+    return Ok(());
+  }
+
+  /// Runs the word `name` against a stack holding one block for each
+  /// argument, the last on top, and quotes the result. Each argument is
+  /// the source of a block's body, so `call("swap", &["A", "B"], ..)`
+  /// reduces `[A] [B] swap`. Arguments are parsed one at a time, so a
+  /// bracket left open in one fails with `Error::Syntax` rather than
+  /// swallowing the rest. A name that would be read as opcodes fails
+  /// with `Error::Shadow`, as it does for `define`.
+  pub fn call(
+    &mut self,
+    name: &str,
+    args: &[&str],
+    time_quota: u64) -> Result<String> {
+    if !NAME_REGEX.is_match(name) {
+      return Err(Error::Syntax);
+    }
+    if is_reserved(name, &self.config) {
+      return Err(Error::Shadow);
+    }
+    let mut terms = Vec::with_capacity(args.len() + 1);
+    for arg in args.iter() {
+      let body = parse(arg, &mut self.heap, &self.config)?;
+      terms.push(self.heap.new_block(body)?);
+    }
+    terms.push(self.heap.new_word(name.into())?);
+    let source = self.heap.new_list(&terms)?;
+    let reduction = self.reduce_hooked(source, time_quota);
+    let target = reduction
+      .and_then(|reduction| self.heap.to_source(reduction.term));
+    self.collect()?;
+    return target;
+  }
+
+  /// Removes the binding for `name`, as the line `~name` would.
+  pub fn undefine(&mut self, name: &str) -> Result<()> {
+    self.forget(name);
+    self.collect()?;
+    return Ok(());
+  }
+
+  /// Binds `name` to a copy of the definition of `source`, made of its
+  /// own nodes rather than sharing the original's. Returns whether
+  /// `source` was defined; if it wasn't, nothing changes. The name is
+  /// checked as `define` checks it.
+  pub fn copy_word(&mut self, name: &str, source: &str) -> Result<bool> {
+    if !NAME_REGEX.is_match(name) {
+      return Err(Error::Syntax);
+    }
+    if is_reserved(name, &self.config) {
+      return Err(Error::Shadow);
+    }
+    let value = match self.tab.get(source) {
+      Some(value) => {
+        *value
+      }
+      None => {
+        return Ok(false);
+      }
+    };
+    let term = self.heap.clone_term(value)?;
+    self.cache.clear();
+    let key: Rc<str> = name.into();
+    if self.tab.insert(key.clone(), term).is_none() {
+      self.order.push(key);
+    }
+    self.collect()?;
+    return Ok(true);
+  }
+
+  /// Reduces two terms and describes the first place where their
+  /// results differ, or returns `None` if they agree.
+  pub fn diff(
+    &mut self,
+    expected: &str,
+    found: &str,
+    time_quota: u64) -> Result<Option<String>> {
+    let expected = parse(expected, &mut self.heap, &self.config)?;
+    let expected = reduce(
+      expected, &mut self.heap, &self.tab, &self.config, time_quota)?;
+    self.heap.push_root(expected.term);
+    let found = parse(found, &mut self.heap, &self.config)
+      .and_then(|found| {
+        reduce(found, &mut self.heap, &self.tab, &self.config, time_quota)
+      });
+    self.heap.pop_root()?;
+    let diff = self.heap.diff(expected.term, found?.term);
+    self.collect()?;
+    return diff;
+  }
+
+  /// Reduces a term like `eval`, but returns the result in two parts:
+  /// the blocks it reduced to, and the code that got stuck along the
+  /// way, each quoted. Code that is still waiting to run when the time
+  /// quota runs out is part of the first.
+  pub fn eval_split(
+    &mut self, src: &str, time_quota: u64) -> Result<(String, String)> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce_split(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let split = reduction.and_then(|reduction| {
+      let stuck = reduction.stuck.ok_or(Error::Bug)?;
+      let term = self.heap.to_source(reduction.term)?;
+      return Ok((term, self.heap.to_source(stuck)?));
+    });
+    self.collect()?;
+    return split;
+  }
+
+  /// Reduces a term and explains each piece of code that got stuck
+  /// along the way, in the order it was reached.
+  pub fn explain(
+    &mut self, src: &str, time_quota: u64) -> Result<Vec<String>> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let explanations = reduction.and_then(|reduction| {
+      return reduction.thunks.iter()
+        .map(|thunk| self.heap.explain(thunk))
+        .collect();
+    });
+    self.collect()?;
+    return explanations;
+  }
+
+  /// Reduces a term and quotes the result, writing each block that
+  /// repeats the one just before it as a copy. See
+  /// `Heap::collapse_duplicates`.
+  pub fn collapse_duplicates(
+    &mut self, src: &str, time_quota: u64) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let target = reduction
+      .and_then(|reduction| self.heap.collapse_duplicates(reduction.term));
+    self.collect()?;
+    return target;
+  }
+
+  /// Reduces a term and quotes the result after `Heap::simplify` has
+  /// taken out what code it can.
+  pub fn simplify(&mut self, src: &str, time_quota: u64) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let target = reduction
+      .and_then(|reduction| self.heap.simplify(reduction.term))
+      .and_then(|term| self.heap.to_source(term));
+    self.collect()?;
+    return target;
+  }
+
+  /// Classifies each token of a line, for highlighting in an editor.
+  /// Words are checked against the library as it is now.
+  pub fn classify(&self, src: &str) -> Vec<(Span, TokenClass)> {
+    let mut target = Vec::new();
+    let mut body = src;
+    let mut body_start = 0;
+    if let Some(data) = POD_INSERT_REGEX.captures(src) {
+      let key = data.get(1).expect("key");
+      let span = Span {
+        start: 0,
+        end: key.end(),
+      };
+      target.push((span, TokenClass::Key));
+      if let Some(doc) = data.get(2) {
+        let span = Span {
+          start: doc.start() - 1,
+          end: doc.end() + 1,
+        };
+        target.push((span, TokenClass::Doc));
+      }
+      body_start = data.get(3).expect("value").start();
+      body = &src[body_start..];
+    } else if let Some(data) = POD_DELETE_REGEX.captures(src) {
+      let key = data.get(1).expect("key");
+      let span = Span {
+        start: 0,
+        end: key.end(),
+      };
+      target.push((span, TokenClass::Key));
+      body_start = src.len();
+      body = "";
+    }
+    for (offset, word) in tokenize(body) {
+      let class = match word {
+        "[" | "]" => TokenClass::Bracket,
+        _ if word.len() == 1 && is_opcode_run(word) => TokenClass::Opcode,
+        _ if self.config.opcode_runs && is_opcode_run(word) => {
+          TokenClass::Opcode
+        }
+        _ if word.len() == 1 && word.chars().all(|x| x.is_lowercase()) => {
+          TokenClass::Invalid
+        }
+        _ if HINT_REGEX.is_match(word) => TokenClass::Hint,
+        _ if self.tab.contains_key(word) => TokenClass::DefinedWord,
+        _ => TokenClass::UndefinedWord,
+      };
+      let span = Span {
+        start: body_start + offset,
+        end: body_start + offset + word.len(),
+      };
+      target.push((span, class));
+    }
+    return target;
+  }
+
+  /// Reduces a term, recording each step taken so that the reduction
+  /// can be replayed later.
+  pub fn eval_recording(
+    &mut self,
+    src: &str,
+    time_quota: u64) -> Result<(String, Vec<TraceEvent>)> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce_recording(
+      source, &mut self.heap, &self.tab, &self.config, time_quota)?;
+    let dst = self.heap.to_source(reduction.term)?;
+    self.collect()?;
+    return Ok((dst, reduction.trace));
+  }
+
+  /// Reduces a term, returning the quoted term after each step, so
+  /// that the way it was reduced can be shown one step at a time.
+  pub fn trace(
+    &mut self, src: &str, time_quota: u64) -> Result<Vec<String>> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce_snapshotting(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    self.collect()?;
+    return Ok(reduction?.snapshots);
+  }
+
+  /// Runs a term for at most `steps` steps and describes the thread as
+  /// it stands, as JSON, for a debugger to show. The object has the
+  /// fuel used as `steps`, the current frame as `frame`, and any
+  /// frames suspended beneath it as `frames`, outermost first. See
+  /// `Frame::to_json` for how a frame is written.
+  pub fn eval_state_json(&mut self, src: &str, steps: u64) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let mut thread = Thread::with_continuation(source, &self.config);
+    let mut target = String::new();
+    let result = run_steps(&mut thread, &mut self.heap, &self.tab, steps)
+      .and_then(|steps| {
+        target.push_str(&format!("{{\"steps\":{},\"frame\":", steps));
+        thread.frame.to_json(&self.heap, &mut target)?;
+        target.push_str(",\"frames\":[");
+        for (index, frame) in thread.frames.iter().enumerate() {
+          if index > 0 {
+            target.push(',');
+          }
+          frame.to_json(&self.heap, &mut target)?;
+        }
+        target.push_str("]}");
+        return Ok(());
+      });
+    self.collect()?;
+    result?;
+    return Ok(target);
+  }
+
+  /// Reduces a term for as many steps as the trace records, checking
+  /// that each step matches. Fails with `Error::Replay` at the first
+  /// step that differs.
+  pub fn replay(
+    &mut self, src: &str, trace: &[TraceEvent]) -> Result<String> {
+    let (dst, replayed) = self.eval_recording(src, trace.len() as u64)?;
+    let step = trace.iter().zip(replayed.iter())
+      .position(|(lhs, rhs)| lhs != rhs)
+      .unwrap_or(replayed.len());
+    if step < trace.len() || step < replayed.len() {
+      return Err(Error::Replay {
+        step: step,
+      });
+    }
+    return Ok(dst);
+  }
+
+  /// Reduces each probe term against this pod's library and another
+  /// pod's, returning whether every result is the same. Each probe is
+  /// a term rather than a line, so it can't change either library.
+  pub fn behaves_like(
+    &mut self,
+    other: &mut Pod,
+    probes: &[&str],
+    time_quota: u64) -> Result<bool> {
+    for probe in probes.iter() {
+      let lhs = self.normalize(probe, time_quota)?;
+      let rhs = other.normalize(probe, time_quota)?;
+      if lhs != rhs {
+        return Ok(false);
+      }
+    }
+    return Ok(true);
+  }
+
+  /// Reduces a term and writes the result to `writer` as it is
+  /// quoted, for results too large to hold as one string.
+  pub fn eval_to_writer<W: std::io::Write>(
+    &mut self,
+    src: &str,
+    time_quota: u64,
+    writer: &mut W) -> Result<()> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = self.reduce_hooked(source, time_quota);
+    let result = reduction.and_then(|reduction| {
+      return quote_to_writer(reduction.term, &self.heap, writer);
+    });
+    self.collect()?;
+    return result;
+  }
+
+  /// Reduces a term and quotes the result in the given style.
+  pub fn eval_styled(
+    &mut self,
+    src: &str,
+    time_quota: u64,
+    style: &QuoteStyle) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = self.reduce_hooked(source, time_quota);
+    let mut dst = String::new();
+    let result = reduction.and_then(|reduction| {
+      return quote_styled(reduction.term, &self.heap, style, &mut dst);
+    });
+    self.collect()?;
+    result?;
+    return Ok(dst);
+  }
+
+  /// Reduces a term and quotes the result for reading, writing any
+  /// block nested more than `max_depth` blocks deep as `…`. See
+  /// `Heap::quote_depth`.
+  pub fn eval_elided(
+    &mut self,
+    src: &str,
+    time_quota: u64,
+    max_depth: usize) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = self.reduce_hooked(source, time_quota);
+    let target = reduction.and_then(|reduction| {
+      return self.heap.quote_depth(reduction.term, max_depth);
+    });
+    self.collect()?;
+    return target;
+  }
+
+  /// Reduces a term and quotes the body of the block on top of the
+  /// stack. Fails with `Error::Tag` if the result doesn't end with a
+  /// block.
+  pub fn eval_block_source(
+    &mut self, src: &str, time_quota: u64) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let mut dst = String::new();
+    let result = reduction.and_then(|reduction| {
+      let elements = self.heap.get_elements(reduction.term)?;
+      let top = *elements.last().ok_or(Error::Tag)?;
+      let body = self.heap.get_block_body(top)?;
+      return quote(body, &self.heap, &mut dst);
+    });
+    self.collect()?;
+    result?;
+    return Ok(dst);
+  }
+
+  /// Reduces a term and quotes the result.
+  fn normalize(&mut self, src: &str, time_quota: u64) -> Result<String> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota);
+    let mut dst = String::new();
+    let result = reduction.and_then(|reduction| {
+      return quote(reduction.term, &self.heap, &mut dst);
+    });
+    self.collect()?;
+    result?;
+    return Ok(dst);
+  }
+
+  /// Reduces a term and keeps the result alive until it is unpinned,
+  /// though it isn't part of the library.
+  pub fn pin(&mut self, src: &str, time_quota: u64) -> Result<PinId> {
+    let source = parse(src, &mut self.heap, &self.config)?;
+    let reduction = reduce(
+      source, &mut self.heap, &self.tab, &self.config, time_quota)?;
+    let index = self.heap.pin(reduction.term);
+    self.collect()?;
+    return Ok(PinId {
+      index: index,
+    });
+  }
+
+  /// Quotes a pinned term, or returns `None` if it was unpinned.
+  pub fn get_pin(&self, id: PinId) -> Result<Option<String>> {
+    match self.heap.pins.get(id.index) {
+      Some(&Some(root)) => {
+        return Ok(Some(self.heap.to_source(root)?));
+      }
+      _ => {
+        return Ok(None);
+      }
+    }
+  }
+
+  /// Lets a pinned term be collected, returning whether it was still
+  /// pinned.
+  pub fn unpin(&mut self, id: PinId) -> bool {
+    match self.heap.pins.get_mut(id.index) {
+      Some(pin) => {
+        return pin.take().is_some();
+      }
+      None => {
+        return false;
+      }
+    }
+  }
+
+  /// Lists the words whose definitions contain a subterm structurally
+  /// equal to `pattern`, such as a particular block.
+  pub fn uses(&mut self, pattern: &str) -> Result<Vec<String>> {
+    let pattern = parse(pattern, &mut self.heap, &self.config)?;
+    let mut keys: Vec<&Rc<str>> = self.tab.keys().collect();
+    keys.sort();
+    let mut target = Vec::new();
+    for key in keys {
+      if !self.heap.find(self.tab[key], pattern)?.is_empty() {
+        target.push(key.to_string());
+      }
+    }
+    self.collect()?;
+    return Ok(target);
+  }
+
+  /// Writes `n` as nested blocks: zero is `[]` and `n + 1` is `[n]`.
+  /// Fails with `Error::Space` if the blocks would be nested deeper
+  /// than the default nesting quota, since a pod couldn't parse them.
+  pub fn church(n: u64) -> Result<String> {
+    use std::convert::TryFrom;
+    let max_depth = Config::default().nesting_quota;
+    let depth = usize::try_from(n).ok()
+      .and_then(|n| n.checked_add(1))
+      .filter(|depth| *depth <= max_depth);
+    match depth {
+      Some(depth) => {
+        return Ok(format!("{}{}", "[".repeat(depth), "]".repeat(depth)));
+      }
+      None => {
+        return Err(Error::Space);
+      }
+    }
+  }
+
+  /// Reads a number written by `church`, such as the output of an
+  /// evaluation, or returns `None` if `src` isn't one.
+  pub fn to_church_number(src: &str) -> Option<u64> {
+    let tokens = tokenize(src);
+    let depth = tokens.iter().take_while(|(_, x)| *x == "[").count();
+    if depth == 0 || tokens.len() != depth * 2 {
+      return None;
+    }
+    if !tokens[depth..].iter().all(|(_, x)| *x == "]") {
+      return None;
+    }
+    return Some(depth as u64 - 1);
+  }
+
+  /// Verifies that a term, with the library's words expanded, uses
+  /// every value exactly once. See `Error::Linear`.
+  pub fn check_linear(&mut self, src: &str) -> Result<()> {
+    let root = parse(src, &mut self.heap, &self.config)?;
+    let result = self.heap.check_linear(root, &self.tab);
+    self.collect()?;
+    return result;
+  }
+
+  /// Infers a term's stack effect: the number of values it takes from
+  /// the stack and the number it leaves behind. Returns `None` when
+  /// the effect depends on data, as when the term applies a block it
+  /// was given.
+  pub fn stack_effect(
+    &mut self, src: &str) -> Result<Option<(usize, usize)>> {
+    let root = parse(src, &mut self.heap, &self.config)?;
+    let result = self.heap.stack_effect(root, &self.tab);
+    self.collect()?;
+    return result;
+  }
+
+  /// Whether a term could take more than `inputs` values from the
+  /// stack, as `Heap::may_underflow` judges it.
+  pub fn may_underflow(&mut self, src: &str, inputs: usize) -> Result<bool> {
+    let root = parse(src, &mut self.heap, &self.config)?;
+    let result = self.heap.new_block(root)
+      .and_then(|block| self.heap.may_underflow(block, inputs, &self.tab));
+    self.collect()?;
+    return result;
+  }
+
+  /// Deletes every node unreachable from the library, returning the
+  /// number of nodes deleted.
+  pub fn collect(&mut self) -> Result<usize> {
+    for pointer in self.tab.values() {
+      self.heap.mark(*pointer)?;
+    }
+    for memo in self.cache.values() {
+      self.heap.mark(memo.source)?;
+      self.heap.mark(memo.term)?;
+    }
+    self.heap.mark_roots()?;
+    return self.heap.sweep();
+  }
+
+  /// The limits applied to this pod's reductions.
+  pub fn config(&self) -> &Config {
+    return &self.config;
+  }
+
+  /// The limits applied to this pod's reductions, for changing. Any
+  /// remembered reductions are forgotten, since they may not hold
+  /// under the new config.
+  pub fn config_mut(&mut self) -> &mut Config {
+    self.cache.clear();
+    return &mut self.config;
+  }
+
+  /// Counts the live nodes of each kind, to show what is filling the
+  /// heap.
+  pub fn count_by_kind(&self) -> KindCounts {
+    return self.heap.count_by_kind();
+  }
+
+  /// The heap's generation, which changes every time garbage is
+  /// collected. See `Heap::generation`.
+  pub fn generation(&self) -> u64 {
+    return self.heap.generation();
+  }
+
+  /// Lists every node in the heap by its slot, reachable or not, with a
+  /// description of each. Garbage left by `eval_no_gc` shows up here
+  /// until the next collection.
+  pub fn live_nodes(&self) -> Vec<(usize, String)> {
+    return self.heap.live_nodes()
+      .map(|(pointer, description)| (pointer.index, description))
+      .collect();
+  }
+
+  /// Counts how many times each word is used across the library's
+  /// definitions, to show which words the library leans on most.
+  pub fn usage_counts(&self) -> Result<HashMap<Rc<str>, usize>> {
+    let mut counts = HashMap::new();
+    for root in self.tab.values() {
+      self.heap.count_words(*root, &mut counts)?;
+    }
+    return Ok(counts);
+  }
+
+  /// Switches the heap to monotonic allocation, so that the same
+  /// program always produces the same pointers. The heap fills up
+  /// rather than reusing collected slots.
+  pub fn set_monotonic(&mut self, is_monotonic: bool) {
+    self.heap.set_monotonic(is_monotonic);
+  }
+
+  /// Draws the library's definitions as a Graphviz `digraph`, to show
+  /// where they share structure.
+  pub fn to_dot(&self) -> Result<String> {
+    let mut keys: Vec<&Rc<str>> = self.tab.keys().collect();
     keys.sort();
+    let roots: Vec<Gc> = keys.iter().map(|key| self.tab[*key]).collect();
+    return self.heap.to_dot(&roots);
+  }
+
+  pub fn to_string(&self) -> Result<String> {
+    let mut target = String::new();
+    let keys: Vec<Rc<str>> = if self.config.keep_order {
+      self.order.clone()
+    } else {
+      let mut keys: Vec<Rc<str>> = self.tab.keys()
+        .cloned().collect();
+      keys.sort();
+      keys
+    };
     for key in keys.iter() {
       let value = self.tab.get(key).unwrap();
-      target.push(':');
-      target.push_str(&key);
-      target.push(' ');
+      self.push_key(key, &mut target);
+      quote(*value, &self.heap, &mut target)?;
+      target.push('\n');
+    }
+    return Ok(target);
+  }
+
+  /// Writes the library to a directory as one file per definition, so
+  /// that changing a definition changes only its own file. Each file
+  /// is named after its word, as `word.md`, and holds the line that
+  /// defines it. See `import_split`.
+  pub fn export_split(&self, dir: &std::path::Path) -> Result<()> {
+    std::fs::create_dir_all(dir).or(Err(Error::Io))?;
+    for (key, value) in self.tab.iter() {
+      let mut target = String::new();
+      self.push_key(key, &mut target);
       quote(*value, &self.heap, &mut target)?;
       target.push('\n');
+      let path = dir.join(format!("{}.md", key));
+      std::fs::write(path, target).or(Err(Error::Io))?;
+    }
+    return Ok(());
+  }
+
+  /// Serializes the library like `to_string`, but first reduces each
+  /// definition against all of the others, using the time quota the
+  /// pod was created with. Libraries with the same definitions
+  /// serialize the same way whatever order they were written in.
+  pub fn to_canonical(&mut self) -> Result<String> {
+    let mut target = String::new();
+    let mut keys: Vec<Rc<str>> = self.tab.keys()
+      .cloned().collect();
+    keys.sort();
+    for key in keys.iter() {
+      let value = self.tab.remove(key).ok_or(Error::Bug)?;
+      self.heap.push_root(value);
+      let reduction = reduce(
+        value, &mut self.heap, &self.tab, &self.config, self.time_quota);
+      self.heap.pop_root()?;
+      self.tab.insert(key.clone(), value);
+      self.push_key(key, &mut target);
+      quote(reduction?.term, &self.heap, &mut target)?;
+      target.push('\n');
+    }
+    self.collect()?;
+    return Ok(target);
+  }
+}
+
+#[test]
+fn primitives() {
+  let space   = 1024;
+  let time    = 1024;
+  let mut pod = Pod::from_string("", space, time).unwrap();
+  let mut check = |source, expected| {
+    println!("{} => {}", source, expected);
+    let target = pod.eval(source, time).unwrap();
+    assert_eq!(expected, &target);
+  };
+  check("", "");
+  check("[A]", "[A]");
+  check("[[A]]", "[[A]]");
+  check("[A] [B]", "[A] [B]");
+  check("a", "a");
+  check("b", "b");
+  check("c", "c");
+  check("d", "d");
+  check("e", "e");
+  check("f", "f");
+  check("g", "g");
+  check("h", "h");
+  check("[A] a", "A");
+  check("[A] b", "[[A]]");
+  check("[A] [B] c", "[A B]");
+  check("[A] c", "[A] c");
+  check("[A] d", "[A] [A]");
+  check("[A] e", "");
+  check("[A] [B] f", "[B] [A]");
+  check("[A] f", "[A] f");
+  check("[A] [B] b c", "[A [B]]");
+  check("[A] g", "[A] g");
+  check("[A] [B] g", "[A] [B] g");
+  check("[A] h", "[A] h");
+  check("i", "i");
+  check("[A] i", "[A] i");
+  check("[A] [B] i", "[A] B [A]");
+  check("[A] [b] i", "[[A]] [A]");
+  check("[A] [B] [e] i", "[A] [B]");
+  check("j", "j");
+  check("[A] j", "[A] j");
+  check("[A] [B] j", "[[A] [B]]");
+  check("[A] [B] j a", "[A] [B]");
+  check("[A B] [C] j", "[[A B] [C]]");
+  check("[A B] [C] c", "[A B C]");
+  check("k", "k");
+  check("[A] [B] k", "[A] [B] k");
+  check("[A] [B] [C] k", "[B] [C] [A]");
+  check("[A] [B] [C] k k", "[C] [A] [B]");
+  check("[A] [B] [C] k k k", "[A] [B] [C]");
+  check("[Z] [A] [B] [C] k", "[Z] [B] [C] [A]");
+  check("l", "l");
+  check("[A] l", "[A] l");
+  check("[[A]] l", "[A]");
+  check("[Z] [[A] [B] f] l", "[Z] [B] [A]");
+  check("[Z] [[A] [B] f] a", "[Z] [B] [A]");
+  check("[Z] [Y] [f] a", "[Y] [Z]");
+  check("[Z] [Y] [f] l", "[Z] [Y] [f] l");
+  check("[Z] [Y] [e [A]] l", "[Z] [Y] [e [A]] l");
+  check("[Z] [Y] [[A] e] l", "[Z] [Y]");
+  check("[Z] [[[A] d] l c] l", "[Z] [A A]");
+}
+
+#[test]
+fn out_of_range_pointers() {
+  let mut heap = Heap::with_capacity(4);
+  let pointer = Gc::new(4, 0);
+  assert_eq!(Err(Error::Null), heap.is_id(pointer));
+  assert_eq!(Err(Error::Null), heap.mark(pointer));
+  let pointer = Gc::new(usize::MAX, 0);
+  assert_eq!(Err(Error::Null), heap.get_block_body(pointer));
+}
+
+#[test]
+fn continuation_quota() {
+  let mut heap = Heap::with_capacity(256);
+  let mut xs = heap.new_word("A".into()).unwrap();
+  for _ in 0..100 {
+    let id = heap.new_id().unwrap();
+    xs = heap.put(Object::Sequence(xs, id)).unwrap();
+  }
+  let tab = HashMap::new();
+  let mut config = Config { continuation_quota: 10, ..Config::default() };
+  let mut thread = Thread::with_continuation(xs, &config);
+  assert_eq!(Err(Error::Space), thread.step(&mut heap, &tab));
+  let generation = heap.generation;
+  let reduction = reduce(xs, &mut heap, &tab, &config, 1024);
+  assert_eq!(Err(Error::Space), reduction.map(|reduction| reduction.steps));
+  assert_eq!(generation, heap.generation);
+  config.continuation_quota = 100;
+  let mut thread = Thread::with_continuation(xs, &config);
+  assert_eq!(Ok(()), thread.step(&mut heap, &tab));
+  config.alloc_quota = Some(0);
+  let source = parse("[A] d", &mut heap, &config).unwrap();
+  let reduction = reduce(source, &mut heap, &tab, &config, 1024);
+  assert_eq!(Err(Error::Space), reduction.map(|reduction| reduction.steps));
+  assert_eq!(generation, heap.generation);
+}
+
+#[test]
+fn eval_instrumented() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let report = pod.eval_instrumented("[A] [B] f", 1024).unwrap();
+  assert_eq!(EvalReport {
+    output: "[B] [A]".to_string(),
+    outcome: Outcome::Completed,
+    steps_used: 3,
+    nodes_allocated: 9,
+    nodes_collected: 9,
+    peak_live: 9,
+    is_cached: false,
+  }, report);
+  pod.eval(":foo [A]", 1024).unwrap();
+  let report = pod.eval_instrumented("foo foo", 1024).unwrap();
+  assert_eq!("[A] [A]", &report.output);
+  assert_eq!(4, report.steps_used);
+  assert_eq!(report.nodes_allocated as usize, report.nodes_collected);
+  assert_eq!(report.peak_live, pod.heap.live + report.nodes_collected);
+}
+
+#[test]
+fn opcode_words() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let baseline = pod.eval_instrumented("[A] d d", 1024).unwrap();
+  let opcode = pod.heap.new_opcode(Opcode::Copy).unwrap();
+  let id = pod.heap.new_id().unwrap();
+  let body = pod.heap.put(Object::Sequence(opcode, id)).unwrap();
+  pod.tab.insert("dup".into(), body);
+  let before = pod.eval_instrumented("[A] dup dup", 1024).unwrap();
+  pod.eval(":dup d", 1024).unwrap();
+  let after = pod.eval_instrumented("[A] dup dup", 1024).unwrap();
+  assert_eq!(baseline.output, before.output);
+  assert_eq!(baseline.output, after.output);
+  assert_eq!(baseline.steps_used, after.steps_used);
+  assert!(after.steps_used < before.steps_used);
+  assert_eq!("d d", &pod.eval("dup dup", 1024).unwrap());
+}
+
+#[test]
+fn monotonic_allocation() {
+  let src = ":foo [A] [B]\n:bar foo f\n~foo\n:baz [C] bar c";
+  let mut pods = vec![];
+  for _ in 0..2 {
+    let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+    pod.eval("[A] [B] [C] e e e", 1024).unwrap();
+    pod.set_monotonic(true);
+    for line in src.lines() {
+      pod.eval(line, 1024).unwrap();
+    }
+    pods.push(pod);
+  }
+  let mut keys: Vec<&Rc<str>> = pods[0].tab.keys().collect();
+  keys.sort();
+  for key in keys {
+    assert_eq!(pods[0].tab.get(key), pods[1].tab.get(key));
+  }
+  assert_eq!(pods[0].to_string(), pods[1].to_string());
+  let pod = &mut pods[0];
+  let frontier = pod.heap.frontier;
+  let pointer = pod.heap.new_id().unwrap();
+  assert_eq!(frontier, pointer.index);
+  assert!(pod.heap.nodes[..frontier].iter().any(|x| x.is_none()));
+}
+
+#[test]
+fn sequence_identities() {
+  let mut heap = Heap::with_capacity(16);
+  let id = heap.new_id().unwrap();
+  let word = heap.new_word("A".into()).unwrap();
+  let allocated = heap.allocated;
+  assert_eq!(word, heap.new_sequence(id, word).unwrap());
+  assert_eq!(word, heap.new_sequence(word, id).unwrap());
+  assert_eq!(id, heap.new_sequence(id, id).unwrap());
+  assert_eq!(allocated, heap.allocated);
+  let xs = heap.new_sequence(word, word).unwrap();
+  let ys = heap.new_sequence(xs, id).unwrap();
+  assert_eq!(xs, ys);
+  assert_eq!(allocated + 1, heap.allocated);
+}
+
+#[test]
+fn try_eval_never_panics() {
+  let alphabet = b"abcdefghijkl[[]]  ():~-AB0\nfoo";
+  let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+  let mut next = || {
+    seed ^= seed << 13;
+    seed ^= seed >> 7;
+    seed ^= seed << 17;
+    return seed;
+  };
+  for _ in 0..4096 {
+    let len = (next() % 64) as usize;
+    let mut bytes = Vec::with_capacity(len);
+    for _ in 0..len {
+      let x = next();
+      if x % 8 == 0 {
+        bytes.push((x >> 8) as u8);
+      } else {
+        bytes.push(alphabet[(x >> 8) as usize % alphabet.len()]);
+      }
+    }
+    let src = String::from_utf8_lossy(&bytes);
+    let _ = try_eval(&src, 128, 256);
+  }
+  let deep = format!("{}{}", "[".repeat(100000), "]".repeat(100000));
+  assert_eq!(Err(Error::Space), try_eval(&deep, 1024, 1024));
+  let deep = format!("{}{} a", "[".repeat(512), "]".repeat(512));
+  assert!(try_eval(&deep, 4096, 1024).is_ok());
+}
+
+#[test]
+fn define() {
+  let mut lhs = Pod::from_string("", 1024, 1024).unwrap();
+  let mut rhs = Pod::from_string("", 1024, 1024).unwrap();
+  lhs.eval(":foo [A] [B] f", 1024).unwrap();
+  lhs.eval(":bar foo c", 1024).unwrap();
+  lhs.eval(":baz [C]", 1024).unwrap();
+  lhs.eval("~baz", 1024).unwrap();
+  rhs.define("foo", "[A] [B] f", 1024).unwrap();
+  rhs.define("bar", "foo c", 1024).unwrap();
+  rhs.define("baz", "[C]", 1024).unwrap();
+  rhs.undefine("baz").unwrap();
+  assert_eq!(lhs.to_string(), rhs.to_string());
+  assert_eq!(lhs.heap.live, rhs.heap.live);
+  assert_eq!(Err(Error::Syntax), rhs.define("Foo", "[A]", 1024));
+  assert_eq!(Err(Error::Syntax), rhs.define("foo bar", "[A]", 1024));
+}
+
+#[test]
+fn emergency_collection() {
+  let src = "[A] b e [A] b e [A] b e [A] b e [A] b e [A] b e [A] b e";
+  let mut heap = Heap::with_capacity(4096);
+  parse(src, &mut heap, &Config::default()).unwrap();
+  let space = heap.live + 2;
+  let tab = HashMap::new();
+  let config = Config::default();
+  let mut heap = Heap::with_capacity(space);
+  let root = parse(src, &mut heap, &Config::default()).unwrap();
+  let mut thread = Thread::with_continuation(root, &config);
+  let mut result = Ok(());
+  while result.is_ok() && thread.has_continuation() {
+    result = thread.step(&mut heap, &tab);
+  }
+  assert_eq!(Err(Error::Space), result);
+  let mut heap = Heap::with_capacity(space);
+  let root = parse(src, &mut heap, &Config::default()).unwrap();
+  let reduction = reduce(root, &mut heap, &tab, &config, 1024).unwrap();
+  let mut dst = String::new();
+  quote(reduction.term, &heap, &mut dst).unwrap();
+  assert_eq!("", &dst);
+  let mut pod = Pod::from_string(":foo [B]", space + 8, 1024).unwrap();
+  assert_eq!("[B]", &pod.eval(&format!("{} foo", src), 1024).unwrap());
+}
+
+#[test]
+fn diff() {
+  let mut heap = Heap::with_capacity(1024);
+  let mut check = |lhs, rhs, expected: Option<&str>| {
+    let lhs = parse(lhs, &mut heap, &Config::default()).unwrap();
+    let rhs = parse(rhs, &mut heap, &Config::default()).unwrap();
+    let diff = heap.diff(lhs, rhs).unwrap();
+    assert_eq!(expected, diff.as_deref());
+  };
+  check("[A] [B] f", "[A] [B] f", None);
+  check("[A [B]] c", "[A [B]] c", None);
+  check("[A] [B] f", "[A] [B] d",
+        Some("at position 2: expected opcode `f`, found opcode `d`"));
+  check("[X [A B a]]", "[X [A B foo]]",
+        Some("at position 0, block body position 1, block body position 2: \
+              expected opcode `a`, found word `foo`"));
+  check("[A] [B]", "[A]",
+        Some("at position 1: expected block `[B]`, found the end of the term"));
+  check("(x) A", "(y) A",
+        Some("at position 0: expected hint `(x)`, found hint `(y)`"));
+}
+
+#[test]
+fn pod_diff() {
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  assert_eq!(Ok(None), pod.diff("foo f", "[B] [A]", 1024));
+  assert_eq!(
+    Ok(Some("at position 0, block body position 0: \
+             expected word `B`, found word `A`".into())),
+    pod.diff("foo f", "foo", 1024));
+  assert!(pod.heap.roots.is_empty());
+}
+
+#[test]
+fn multi_line_records() {
+  let src = "\
+:foo [A]
+:bar [B]
+  foo
+  f
+
+  [C]
+:baz foo bar";
+  assert_eq!(
+    vec![":foo [A]", ":bar [B] foo f", "", "  [C]", ":baz foo bar"],
+    records(src));
+  let pod = Pod::from_string(src, 1024, 1024).unwrap();
+  assert_eq!(
+    Ok(":bar [A] [B]\n:baz [A] [A] [B]\n:foo [A]\n".to_string()),
+    pod.to_string());
+}
+
+#[test]
+fn count_by_kind() {
+  let mut heap = Heap::with_capacity(64);
+  parse("[A] [B (x)] c", &mut heap, &Config::default()).unwrap();
+  assert_eq!(KindCounts {
+    id: 0,
+    opcode: 1,
+    word: 2,
+    hint: 1,
+    block: 2,
+    sequence: 3,
+  }, heap.count_by_kind());
+  let pod = Pod::from_string(":foo [A] d", 64, 64).unwrap();
+  assert_eq!(KindCounts {
+    word: 1,
+    block: 1,
+    sequence: 1,
+    ..KindCounts::default()
+  }, pod.count_by_kind());
+}
+
+#[test]
+fn eval_no_gc() {
+  let src = [":foo [A] [B]", "foo f", ":bar foo c", "bar d", "~foo", "[C] e"];
+  let mut eager = Pod::from_string("", 1024, 1024).unwrap();
+  let mut lazy = Pod::from_string("", 1024, 1024).unwrap();
+  for line in src.iter() {
+    let expected = eager.eval(line, 1024).unwrap();
+    assert_eq!(expected, lazy.eval_no_gc(line, 1024).unwrap());
+  }
+  assert!(lazy.heap.live > eager.heap.live);
+  lazy.collect().unwrap();
+  assert_eq!(eager.heap.live, lazy.heap.live);
+  assert_eq!(eager.count_by_kind(), lazy.count_by_kind());
+  assert_eq!(eager.to_string(), lazy.to_string());
+}
+
+#[test]
+fn outcomes() {
+  let mut pod = Pod::from_string(":foo [A] foo", 1024, 1024).unwrap();
+  let mut check = |src, output: &str, outcome| {
+    let report = pod.eval_instrumented(src, 8).unwrap();
+    assert_eq!(output, &report.output);
+    assert_eq!(outcome, report.outcome);
+  };
+  check("[A] [B] f", "[B] [A]", Outcome::Completed);
+  check("[A] e", "", Outcome::Completed);
+  check("[A] c [B] bar a", "[A] c [B] bar a", Outcome::Stuck {
+    first_stuck: "c".into(),
+  });
+  check("bar [A] a", "bar A", Outcome::Stuck {
+    first_stuck: "bar".into(),
+  });
+  check("foo", "[A] [A] [A] [A] foo", Outcome::QuotaExhausted);
+  check("foo [B]", "[A] [A] [A] [A] foo [B]", Outcome::QuotaExhausted);
+  check(":baz a", ":baz a", Outcome::Stuck {
+    first_stuck: "a".into(),
+  });
+}
+
+#[test]
+fn check_linear() {
+  let mut pod = Pod::from_string(":foo f\n:bar [e]", 1024, 1024).unwrap();
+  assert_eq!(Ok(()), pod.check_linear(""));
+  assert_eq!(Ok(()), pod.check_linear("[A] [B] f c"));
+  assert_eq!(Ok(()), pod.check_linear("f [b] a c"));
+  assert_eq!(Ok(()), pod.check_linear("[A] [foo] a"));
+  assert_eq!(Ok(()), pod.check_linear("[[f] [b] c] b a a"));
+  assert_eq!(Ok(()), pod.check_linear("[A] [B] j a f"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("[A] d"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("[A] e"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("f [f e] a"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("bar a"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("[A] [B] i"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("[A] baz"));
+  assert_eq!(Err(Error::Linear), pod.check_linear("a"));
+}
+
+#[test]
+fn to_canonical() {
+  let lhs = ":foo [A] [B]\n:bar foo f\n:baz [bar] a";
+  let rhs = ":baz [bar] a\n:bar foo f\n:foo [A] [B]";
+  let mut lhs = Pod::from_string(lhs, 1024, 1024).unwrap();
+  let mut rhs = Pod::from_string(rhs, 1024, 1024).unwrap();
+  assert!(lhs.to_string() != rhs.to_string());
+  let expected = ":bar [B] [A]\n:baz [B] [A]\n:foo [A] [B]\n";
+  assert_eq!(Ok(expected.to_string()), lhs.to_canonical());
+  assert_eq!(Ok(expected.to_string()), rhs.to_canonical());
+  let mut pod = Pod::from_string(":foo [A] foo", 1024, 1024).unwrap();
+  assert_eq!(Ok(":foo [A] foo\n".to_string()), pod.to_canonical());
+}
+
+#[test]
+fn explain() {
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  assert_eq!(Ok(vec![]), pod.explain("foo f", 1024));
+  assert_eq!(Ok(vec![
+    "word `bar` is undefined".to_string(),
+  ]), pod.explain("bar foo", 1024));
+  assert_eq!(Ok(vec![
+    "`a` at position 0 needs one block on the stack but found none"
+      .to_string(),
+    "`c` at position 2 needs two blocks on the stack but found one"
+      .to_string(),
+    "`f` at position 4 needs two blocks on the stack but found one"
+      .to_string(),
+    "word `bar` is undefined".to_string(),
+    "`g` at position 6 is a proposition, which never reduces"
+      .to_string(),
+  ]), pod.explain("a foo e c [C] f bar g", 1024));
+  assert_eq!(Ok(vec![
+    "`k` at position 2 needs three blocks on the stack but found two"
+      .to_string(),
+  ]), pod.explain("foo k", 1024));
+}
+
+#[test]
+fn classify() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let classes = |pod: &Pod, src| {
+    return pod.classify(src).into_iter()
+      .map(|(span, class)| (span.start, span.end, class))
+      .collect::<Vec<_>>();
+  };
+  assert_eq!(vec![
+    (0, 1, TokenClass::Bracket),
+    (1, 4, TokenClass::UndefinedWord),
+    (4, 5, TokenClass::Bracket),
+    (6, 7, TokenClass::Opcode),
+    (8, 13, TokenClass::Hint),
+    (14, 15, TokenClass::Invalid),
+  ], classes(&pod, "[foo] a (bar) x"));
+  assert_eq!(vec![
+    (0, 4, TokenClass::Key),
+    (5, 6, TokenClass::Bracket),
+    (6, 7, TokenClass::UndefinedWord),
+    (7, 8, TokenClass::Bracket),
+  ], classes(&pod, ":foo [A]"));
+  pod.eval(":foo [A]", 1024).unwrap();
+  assert_eq!(vec![
+    (0, 1, TokenClass::Bracket),
+    (1, 4, TokenClass::DefinedWord),
+    (4, 5, TokenClass::Bracket),
+    (6, 7, TokenClass::Opcode),
+  ], classes(&pod, "[foo] a"));
+  assert_eq!(vec![
+    (0, 4, TokenClass::Key),
+  ], classes(&pod, "~foo"));
+}
+
+#[test]
+fn strict_arity() {
+  let mut pod = Pod::from_string(":foo e", 1024, 1024).unwrap();
+  assert_eq!(Ok("e".to_string()), pod.eval("e", 1024));
+  assert_eq!(Ok("[A] c".to_string()), pod.eval("[A] c", 1024));
+  pod.config_mut().strict_arity = true;
+  let error = Error::Underflow {
+    needed: 1,
+    found: 0,
+  };
+  assert_eq!(Err(error), pod.eval("e", 1024));
+  assert_eq!(Err(error), pod.eval("foo", 1024));
+  let error = Error::Underflow {
+    needed: 2,
+    found: 1,
+  };
+  assert_eq!(Err(error), pod.eval("[A] c", 1024));
+  let error = Error::Underflow {
+    needed: 3,
+    found: 2,
+  };
+  assert_eq!(Err(error), pod.eval("[A] [B] k", 1024));
+  assert_eq!(Ok("".to_string()), pod.eval("[A] e", 1024));
+  assert_eq!(Ok("bar [A] g".to_string()), pod.eval("bar [A] g", 1024));
+}
+
+#[test]
+fn error_classification() {
+  let errors = [
+    (Error::Time, false, true),
+    (Error::Space, false, true),
+    (Error::Tag, true, false),
+    (Error::Stub, false, false),
+    (Error::Bug, true, false),
+    (Error::Null, true, false),
+    (Error::Assert, true, false),
+    (Error::Syntax, false, true),
+    (Error::Underflow { needed: 1, found: 0 }, false, true),
+    (Error::Home, false, false),
+    (Error::Linear, false, true),
+    (Error::Arity { position: 0 }, false, true),
+    (Error::Replay { step: 0 }, false, false),
+    (Error::Forbidden, false, true),
+    (Error::Io, false, false),
+    (Error::Diverge, false, true),
+    (Error::Shadow, false, true),
+    (Error::Expansion, false, true),
+  ];
+  for (error, is_fatal, is_user_error) in errors.iter() {
+    assert_eq!(*is_fatal, error.is_fatal(), "{:?}", error);
+    assert_eq!(*is_user_error, error.is_user_error(), "{:?}", error);
+  }
+}
+
+#[test]
+fn strategies() {
+  let src = ":foo [A] [B] f\n:bar [foo] a";
+  let mut outer = Pod::from_string(src, 1024, 1024).unwrap();
+  let mut inner = Pod::from_string(src, 1024, 1024).unwrap();
+  inner.config_mut().strategy = Strategy::Innermost;
+  let mut check = |src, outer_output: &str, inner_output: &str| {
+    let outer = outer.eval_instrumented(src, 1024).unwrap();
+    let inner = inner.eval_instrumented(src, 1024).unwrap();
+    assert_eq!(outer_output, &outer.output);
+    assert_eq!(inner_output, &inner.output);
+    return (outer.steps_used, inner.steps_used);
+  };
+  assert_eq!((3, 7), check("foo", "[B] [A]", "[B] [A]"));
+  assert_eq!((5, 16), check("[foo] a", "[B] [A]", "[B] [A]"));
+  assert_eq!((1, 9), check("[foo]", "[foo]", "[[B] [A]]"));
+  assert_eq!((3, 11), check("[bar] d e", "[bar]", "[[B] [A]]"));
+}
+
+#[test]
+fn check_arity() {
+  let mut pod = Pod::from_string(":foo [(2-1) c]", 1024, 1024).unwrap();
+  pod.config_mut().check_arity = true;
+  assert_eq!(Ok("[A B]".to_string()), pod.eval("[A] [B] foo a", 1024));
+  assert_eq!(Ok("[(1-2) [A] f]".to_string()), pod.eval("[(1-2) [A] f]", 1024));
+  assert_eq!(Ok("[(0-0) A a]".to_string()), pod.eval("[(0-0) A a]", 1024));
+  assert_eq!(Ok("[(x) c]".to_string()), pod.eval("[(x) c]", 1024));
+  assert_eq!(Err(Error::Arity {
+    position: 1,
+  }), pod.eval("[A] [(1-1) d]", 1024));
+  assert_eq!(Err(Error::Arity {
+    position: 0,
+  }), pod.eval(":bar [(2-1) f]", 1024));
+  pod.config_mut().check_arity = false;
+  assert_eq!(Ok("[(1-1) d]".to_string()), pod.eval("[(1-1) d]", 1024));
+}
+
+#[test]
+fn to_dot() {
+  let mut pod = Pod::from_string("", 64, 64).unwrap();
+  pod.set_monotonic(true);
+  pod.eval(":foo [A] d", 64).unwrap();
+  let expected = "digraph {
+  n5 [label=\"seq\"];
+  n5 -> n1;
+  n5 -> n1;
+  n1 [label=\"[]\"];
+  n1 -> n0;
+  n0 [label=\"A\"];
+}
+";
+  assert_eq!(Ok(expected.to_string()), pod.to_dot());
+}
+
+#[test]
+fn from_string_deferred() {
+  let src = ":foo [A] [B]\n:bar foo f\n:foo [C]\n:baz bar c\n~bar\n:foo foo d";
+  let eager = Pod::from_string(src, 1024, 1024).unwrap();
+  let deferred = Pod::from_string_deferred(src, 1024, 1024).unwrap();
+  assert_eq!(eager.heap.live, deferred.heap.live);
+  assert_eq!(eager.count_by_kind(), deferred.count_by_kind());
+  assert_eq!(eager.to_string(), deferred.to_string());
+  let src = ":foo [A] [B] c\n".repeat(16);
+  assert!(Pod::from_string_deferred(&src, 64, 64).is_ok());
+}
+
+#[test]
+fn replay() {
+  let mut pod = Pod::from_string(":foo [B] c", 1024, 1024).unwrap();
+  let (output, trace) = pod.eval_recording("[A] foo a", 1024).unwrap();
+  assert_eq!("A B", &output);
+  let events: Vec<String> = trace.iter().map(|x| x.to_string()).collect();
+  assert_eq!(vec![
+    "1 [A]", "1 foo", "2 [B]", "1 c", "0 a", "0 A", "0 B",
+  ], events);
+  let parsed: Vec<TraceEvent> = events.iter()
+    .map(|x| x.parse().unwrap()).collect();
+  assert_eq!(trace, parsed);
+  assert_eq!(Ok(output), pod.replay("[A] foo a", &parsed));
+  assert_eq!(Ok("[A B] a".to_string()), pod.replay("[A] foo a", &trace[..4]));
+  pod.eval(":foo [C] c", 1024).unwrap();
+  assert_eq!(Err(Error::Replay {
+    step: 2,
+  }), pod.replay("[A] foo a", &trace));
+  assert_eq!(Err(Error::Replay {
+    step: 1,
+  }), pod.replay("[A] [B] c", &trace[..4]));
+  assert_eq!(Err(Error::Syntax), "foo".parse::<TraceEvent>());
+}
+
+#[test]
+fn docs() {
+  let src = ":dup \"copy the top block\" d\n:drop e";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  assert_eq!(Some("copy the top block".to_string()), pod.doc("dup"));
+  assert_eq!(None, pod.doc("drop"));
+  assert_eq!(None, pod.doc("swap"));
+  let src = pod.to_string().unwrap();
+  assert_eq!(":drop e\n:dup \"copy the top block\" d\n", &src);
+  let copy = Pod::from_string(&src, 1024, 1024).unwrap();
+  assert_eq!(Ok(src), copy.to_string());
+  assert_eq!(Ok("[A] [A]".to_string()), pod.eval("[A] dup", 1024));
+  assert_eq!(
+    Ok(":swap \"\" f".to_string()), pod.eval(":swap \"\" f", 1024));
+  assert_eq!(Some("".to_string()), pod.doc("swap"));
+  assert_eq!(Ok(":dup d".to_string()), pod.eval(":dup d", 1024));
+  assert_eq!(None, pod.doc("dup"));
+  pod.eval(":drop \"discard\" e", 1024).unwrap();
+  pod.eval("~drop", 1024).unwrap();
+  assert_eq!(None, pod.doc("drop"));
+  assert_eq!(vec![
+    (Span { start: 0, end: 4 }, TokenClass::Key),
+    (Span { start: 5, end: 10 }, TokenClass::Doc),
+    (Span { start: 11, end: 12 }, TokenClass::Opcode),
+  ], pod.classify(":dup \"doc\" d"));
+}
+
+#[test]
+fn nesting_quota() {
+  let mut pod = Pod::from_string("", 1 << 16, 1024).unwrap();
+  pod.config_mut().nesting_quota = 4;
+  assert_eq!(Ok("[[[[A]]]]".to_string()), pod.eval("[[[[A]]]] [B] e", 1024));
+  assert_eq!(Ok("[[A]] [[B]]".to_string()), pod.eval("[[A]] [[B]]", 1024));
+  assert_eq!(Err(Error::Space), pod.eval("[[[[[A]]]]]", 1024));
+  assert_eq!(Err(Error::Space), pod.eval(":foo [[[[[", 1024));
+  let deep = "[".repeat(1 << 20);
+  assert_eq!(Err(Error::Space), try_eval(&deep, 1 << 16, 1024));
+}
+
+#[test]
+fn behaves_like() {
+  let probes = ["[A] [B] flip", "[A] dup flip", "[A] [B] dup"];
+  let lhs = ":flip f\n:dup d";
+  let rhs = ":flip f f f\n:dup [d] a";
+  let bad = ":flip f f\n:dup d";
+  let mut lhs = Pod::from_string(lhs, 1024, 1024).unwrap();
+  let mut rhs = Pod::from_string(rhs, 1024, 1024).unwrap();
+  let mut bad = Pod::from_string(bad, 1024, 1024).unwrap();
+  assert!(lhs.to_string() != rhs.to_string());
+  assert_eq!(Ok(true), lhs.behaves_like(&mut rhs, &probes, 1024));
+  assert_eq!(Ok(true), rhs.behaves_like(&mut lhs, &probes, 1024));
+  assert_eq!(Ok(false), lhs.behaves_like(&mut bad, &probes, 1024));
+  assert_eq!(Ok(true), lhs.behaves_like(&mut bad, &probes[2..], 1024));
+}
+
+#[test]
+fn eval_block_source() {
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  assert_eq!(Ok("B".to_string()), pod.eval_block_source("foo", 1024));
+  assert_eq!(Ok("B A".to_string()), pod.eval_block_source("foo f c", 1024));
+  assert_eq!(Ok("".to_string()), pod.eval_block_source("[]", 1024));
+  assert_eq!(Err(Error::Tag), pod.eval_block_source("foo bar", 1024));
+  assert_eq!(Err(Error::Tag), pod.eval_block_source("foo e e", 1024));
+}
+
+#[test]
+fn on_undefined() {
+  use std::cell::RefCell;
+  let printed = Rc::new(RefCell::new(Vec::new()));
+  let mut pod = Pod::from_string(":hello [hi] print", 1024, 1024).unwrap();
+  assert_eq!(Ok(":hello [hi] print\n".to_string()), pod.to_string());
+  let log = printed.clone();
+  pod.on_undefined(move |name, stack| {
+    match name {
+      "print" => {
+        match stack.pop()? {
+          Some(body) => {
+            log.borrow_mut().push(body);
+            return Ok(true);
+          }
+          None => {
+            return Ok(false);
+          }
+        }
+      }
+      "answer" => {
+        stack.push("forty two")?;
+        return Ok(true);
+      }
+      _ => {
+        return Ok(false);
+      }
     }
-    return Ok(target);
+  });
+  assert_eq!(Ok("[A]".to_string()), pod.eval("[A] [B C] print", 1024));
+  assert_eq!(Ok("".to_string()), pod.eval("hello", 1024));
+  assert_eq!(Ok("print foo".to_string()), pod.eval("print foo", 1024));
+  assert_eq!(Ok("[forty two]".to_string()), pod.eval("answer [B] e", 1024));
+  assert_eq!(vec!["B C", "hi"], *printed.borrow());
+}
+
+#[test]
+fn find() {
+  let mut heap = Heap::with_capacity(64);
+  let config = Config::default();
+  let root = parse("[A] [B] [A]", &mut heap, &config).unwrap();
+  let pattern = parse("[A]", &mut heap, &config).unwrap();
+  let found = heap.find(root, pattern).unwrap();
+  let elements = heap.get_elements(root).unwrap();
+  assert_eq!(vec![elements[0], elements[2]], found);
+  let pattern = parse("A", &mut heap, &config).unwrap();
+  assert_eq!(2, heap.find(root, pattern).unwrap().len());
+  let pattern = parse("[B] [A]", &mut heap, &config).unwrap();
+  assert_eq!(1, heap.find(root, pattern).unwrap().len());
+  let pattern = parse("[C]", &mut heap, &config).unwrap();
+  assert_eq!(0, heap.find(root, pattern).unwrap().len());
+  let src = ":foo [A] d\n:bar [[A] a]\n:baz [B]";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  assert_eq!(Ok(vec!["bar".to_string(), "foo".to_string()]), pod.uses("[A]"));
+  assert_eq!(Ok(vec!["baz".to_string()]), pod.uses("B"));
+}
+
+#[test]
+fn allowed_opcodes() {
+  let mut pod = Pod::from_string(":dup d\n:twice [dup] a", 1024, 1024).unwrap();
+  assert_eq!(Ok("[A] [A]".to_string()), pod.eval("[A] dup", 1024));
+  pod.config_mut().allowed = OpcodeSet::all().without('d');
+  assert_eq!(Err(Error::Forbidden), pod.eval("[A] d", 1024));
+  assert_eq!(Err(Error::Forbidden), pod.eval("[A] dup", 1024));
+  assert_eq!(Err(Error::Forbidden), pod.eval("[A] twice", 1024));
+  assert_eq!(Err(Error::Forbidden), pod.eval("d", 1024));
+  assert_eq!(Ok("[d]".to_string()), pod.eval("[A] e [d]", 1024));
+  assert_eq!(Ok("[B] [A]".to_string()), pod.eval("[A] [B] f", 1024));
+  pod.config_mut().allowed = OpcodeSet::none().with('a').with('f');
+  assert_eq!(Ok("[B] [A]".to_string()), pod.eval("[A] [B] [f] a", 1024));
+  assert_eq!(Err(Error::Forbidden), pod.eval("[A] e", 1024));
+  assert!(!OpcodeSet::all().contains('A'));
+}
+
+#[test]
+fn church() {
+  for n in 0..8 {
+    let src = Pod::church(n).unwrap();
+    assert_eq!(Some(n), Pod::to_church_number(&src));
   }
+  assert_eq!(Ok("[]".to_string()), Pod::church(0));
+  assert_eq!(Ok("[[[]]]".to_string()), Pod::church(2));
+  assert_eq!(Err(Error::Space), Pod::church(u64::MAX));
+  assert_eq!(Err(Error::Space), Pod::church(u64::MAX - 1));
+  let max = Config::default().nesting_quota as u64 - 1;
+  let src = Pod::church(max).unwrap();
+  assert_eq!(Some(max), Pod::to_church_number(&src));
+  let mut pod = Pod::from_string("", 1 << 12, 1024).unwrap();
+  assert_eq!(Ok(src), pod.eval(&Pod::church(max).unwrap(), 1024));
+  assert_eq!(Err(Error::Space), Pod::church(max + 1));
+  let mut pod = Pod::from_string(":succ b", 1024, 1024).unwrap();
+  let two = Pod::church(2).unwrap();
+  let three = pod.eval(&format!("{} succ", two), 1024).unwrap();
+  assert_eq!(Some(3), Pod::to_church_number(&three));
+  assert_eq!(Some(1), Pod::to_church_number(" [ [ ] ] "));
+  assert_eq!(None, Pod::to_church_number(""));
+  assert_eq!(None, Pod::to_church_number("[[]"));
+  assert_eq!(None, Pod::to_church_number("[] []"));
+  assert_eq!(None, Pod::to_church_number("[[A]]"));
 }
 
 #[test]
-fn primitives() {
-  let space   = 1024;
-  let time    = 1024;
-  let mut pod = Pod::from_string("", space, time).unwrap();
-  let mut check = |source, expected| {
-    println!("{} => {}", source, expected);
-    let target = pod.eval(source, time).unwrap();
-    assert_eq!(expected, &target);
+fn compact_term() {
+  let mut heap = Heap::with_capacity(64);
+  let config = Config::default();
+  let live = |heap: &mut Heap, root| {
+    heap.push_root(root);
+    heap.mark_roots().unwrap();
+    heap.sweep().unwrap();
+    heap.pop_root().unwrap();
+    return heap.live;
   };
-  check("", "");
-  check("[A]", "[A]");
-  check("[[A]]", "[[A]]");
-  check("[A] [B]", "[A] [B]");
-  check("a", "a");
-  check("b", "b");
-  check("c", "c");
-  check("d", "d");
-  check("e", "e");
-  check("f", "f");
-  check("g", "g");
-  check("h", "h");
-  check("[A] a", "A");
-  check("[A] b", "[[A]]");
-  check("[A] [B] c", "[A B]");
-  check("[A] c", "[A] c");
-  check("[A] d", "[A] [A]");
-  check("[A] e", "");
-  check("[A] [B] f", "[B] [A]");
-  check("[A] f", "[A] f");
-  check("[A] [B] b c", "[A [B]]");
-  check("[A] g", "[A] g");
-  check("[A] [B] g", "[A] [B] g");
-  check("[A] h", "[A] h");
+  let id = heap.new_id().unwrap();
+  let a = parse("[A] [B] c", &mut heap, &config).unwrap();
+  let body = heap.put(Object::Sequence(id, a)).unwrap();
+  let block = heap.new_block(body).unwrap();
+  let xs = heap.put(Object::Sequence(block, id)).unwrap();
+  let xs = heap.put(Object::Sequence(id, xs)).unwrap();
+  let root = heap.put(Object::Sequence(xs, block)).unwrap();
+  let mut before = String::new();
+  quote(root, &heap, &mut before).unwrap();
+  let before_live = live(&mut heap, root);
+  let compact = heap.compact_term(root).unwrap();
+  let mut after = String::new();
+  quote(compact, &heap, &mut after).unwrap();
+  assert_eq!(before.replace(" ", ""), after.replace(" ", ""));
+  assert_eq!("[[A] [B] c] [[A] [B] c]", &after);
+  assert!(live(&mut heap, compact) < before_live);
+  let elements = heap.get_elements(compact).unwrap();
+  assert_eq!(2, elements.len());
+  assert_eq!(elements[0], elements[1]);
+  let a = parse("[A] [B [C]] c", &mut heap, &config).unwrap();
+  assert_eq!(Ok(a), heap.compact_term(a));
+  let id = heap.new_id().unwrap();
+  assert_eq!(Ok(id), heap.compact_term(id));
+}
+
+#[test]
+fn eval_to_writer() {
+  let mut heap = Heap::with_capacity(64);
+  let config = Config::default();
+  let root = parse("[A] [[B] (x) c] d foo", &mut heap, &config).unwrap();
+  let mut expected = String::new();
+  quote(root, &heap, &mut expected).unwrap();
+  let mut bytes: Vec<u8> = Vec::new();
+  quote_to_writer(root, &heap, &mut bytes).unwrap();
+  assert_eq!(expected.as_bytes(), &bytes[..]);
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  let mut bytes: Vec<u8> = Vec::new();
+  pod.eval_to_writer("foo f d", 1024, &mut bytes).unwrap();
+  assert_eq!(b"[B] [A] [A]", &bytes[..]);
+  let mut full = [0u8; 4];
+  let mut writer = &mut full[..];
+  assert_eq!(Err(Error::Io), pod.eval_to_writer("foo", 1024, &mut writer));
+}
+
+#[test]
+fn probe_quota() {
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  pod.config_mut().probe_quota = Some(64);
+  assert_eq!(Err(Error::Diverge), pod.eval(":loop loop", 1024));
+  assert_eq!(Ok("loop".to_string()), pod.eval("loop", 1024));
+  assert_eq!(Err(Error::Diverge), pod.eval(":forever [B] forever", 1024));
+  assert_eq!(Ok("forever".to_string()), pod.eval("forever", 1024));
+  assert_eq!(Ok(":foo [A] [B]".to_string()), pod.eval(":foo foo [B]", 1024));
+  assert_eq!(Ok(":id [a]".to_string()), pod.eval(":id [a]", 1024));
+  assert_eq!(Ok(":copy d".to_string()), pod.eval(":copy d", 1024));
+  assert_eq!(Err(Error::Diverge), pod.define("again", "again", 1024));
+  pod.config_mut().probe_quota = None;
+  assert_eq!(Ok(":loop loop".to_string()), pod.eval(":loop loop", 1024));
+}
+
+#[test]
+fn fork() {
+  let mut pod = Pod::from_string(":foo \"two blocks\" [A] [B]", 1024, 1024).unwrap();
+  pod.eval("foo foo foo", 1024).unwrap();
+  let mut fork = pod.fork().unwrap();
+  assert!(fork.heap.live <= pod.heap.live);
+  assert_eq!(pod.to_string(), fork.to_string());
+  fork.eval(":bar foo f", 1024).unwrap();
+  fork.eval("~foo", 1024).unwrap();
+  assert_eq!(Ok(":bar [B] [A]\n".to_string()), fork.to_string());
+  assert_eq!(Ok(":foo \"two blocks\" [A] [B]\n".to_string()), pod.to_string());
+  assert_eq!(Ok("bar".to_string()), pod.eval("bar", 1024));
+  assert_eq!(Ok("foo".to_string()), fork.eval("foo", 1024));
+}
+
+#[test]
+fn on_low_fuel() {
+  use std::cell::RefCell;
+  let calls = Rc::new(RefCell::new(Vec::new()));
+  let src = ":foo [A] [B] f\n:bar foo foo foo foo";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  let report = pod.eval_instrumented("bar", 4).unwrap();
+  assert_eq!(Outcome::QuotaExhausted, report.outcome);
+  let log = calls.clone();
+  pod.on_low_fuel(2, move |steps| {
+    log.borrow_mut().push(steps);
+    if log.borrow().len() < 3 {
+      return Fuel::Continue(4);
+    }
+    return Fuel::Stop;
+  });
+  let report = pod.eval_instrumented("bar", 4).unwrap();
+  assert_eq!(Outcome::Completed, report.outcome);
+  assert_eq!(9, report.steps_used);
+  assert_eq!(vec![2, 6], *calls.borrow());
+  calls.borrow_mut().clear();
+  let report = pod.eval_instrumented("bar bar", 4).unwrap();
+  assert_eq!(Outcome::QuotaExhausted, report.outcome);
+  assert_eq!(10, report.steps_used);
+  assert_eq!(vec![2, 6, 10], *calls.borrow());
+  let log = calls.clone();
+  pod.on_low_fuel(10, move |steps| {
+    log.borrow_mut().push(steps);
+    if log.borrow().len() < 3 {
+      return Fuel::Continue(4);
+    }
+    return Fuel::Stop;
+  });
+  calls.borrow_mut().clear();
+  let report = pod.eval_instrumented("bar bar", 4).unwrap();
+  assert_eq!(Outcome::QuotaExhausted, report.outcome);
+  assert_eq!(8, report.steps_used);
+  assert_eq!(vec![0, 4, 8], *calls.borrow());
+}
+
+#[test]
+fn alloc_quota() {
+  let mut pod = Pod::from_string(":foo [A] b b b b", 1024, 1024).unwrap();
+  pod.config_mut().alloc_quota = Some(8);
+  assert_eq!(Ok("[[[[[A]]]]]".to_string()), pod.eval("foo", 1024));
+  assert_eq!(Ok("[A] [A] [A]".to_string()), pod.eval("[A] d d", 1024));
+  assert_eq!(Err(Error::Space), pod.eval("foo b b b b b b b b", 1024));
+  assert_eq!(Err(Error::Space), pod.eval("[A] [b] d c c c c c c c a", 1024));
+  assert_eq!(Ok("[[[[[[A]]]]]]".to_string()), pod.eval("foo b", 1024));
+  pod.config_mut().alloc_quota = None;
+  let src = "foo b b b b b b b b";
+  assert_eq!(Ok("[[[[[[[[[[[[[A]]]]]]]]]]]]]".to_string()), pod.eval(src, 1024));
+}
+
+#[test]
+fn opcode_runs() {
+  let mut pod = Pod::from_string(":ab [B]", 1024, 1024).unwrap();
+  assert_eq!(Ok("[A]".to_string()), pod.eval("[A] ab e", 1024));
+  assert_eq!(Ok("[A] [f] [B]".to_string()), pod.eval("[A] [f] ab", 1024));
+  pod.config_mut().opcode_runs = true;
+  assert_eq!(Ok("A b e".to_string()), pod.eval("[A] ab e", 1024));
+  assert_eq!(Ok("[A] [[B]]".to_string()), pod.eval("[B] [A] [f] ab", 1024));
+  assert_eq!(Ok("[A] [A]".to_string()), pod.eval("[A] [d] ab a", 1024));
+  assert_eq!(Ok("[A B]".to_string()), pod.eval("[A] [B] [[c]] aa", 1024));
+  assert_eq!(Ok("abz".to_string()), pod.eval("abz", 1024));
+  assert_eq!(vec![
+    (Span { start: 0, end: 2 }, TokenClass::Opcode),
+  ], pod.classify("ab"));
+}
+
+#[test]
+fn cyclic_graphs() {
+  let mut heap = Heap::with_capacity(4);
+  let id = heap.new_id().unwrap();
+  let block = heap.new_block(id).unwrap();
+  let root = heap.new_sequence(block, block).unwrap();
+  let object = Object::Block(root);
+  let node = Node::new(object, heap.generation, heap.allocated, 1);
+  heap.nodes[block.index] = Some(node);
+  let mut bytes = Vec::new();
+  assert_eq!(Err(Error::Bug), quote_to_writer(root, &heap, &mut bytes));
+  assert!(bytes.is_empty());
+  let mut buf = String::new();
+  let style = QuoteStyle::default();
+  assert_eq!(Err(Error::Bug), quote_styled(root, &heap, &style, &mut buf));
+  assert_eq!(Err(Error::Bug), heap.to_source(block));
+  assert_eq!(Err(Error::Bug), heap.quote_depth(root, 8));
+  assert_eq!(Err(Error::Bug), heap.get_elements(root));
+  assert_eq!(Err(Error::Bug), heap.hash_term(root));
+  assert_eq!(Err(Error::Bug), heap.clone_term(root));
+  heap.push_root(root);
+  assert_eq!(Err(Error::Bug), heap.mark_roots());
+}
+
+#[test]
+fn pins() {
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  let live = pod.heap.live;
+  let id = pod.pin("foo f b", 1024).unwrap();
+  let pinned = pod.heap.live;
+  assert!(pinned > live);
+  for src in ["foo c", ":bar foo", "~bar", "[C] d"].iter() {
+    pod.eval(src, 1024).unwrap();
+  }
+  assert_eq!(pinned, pod.heap.live);
+  assert_eq!(Ok(Some("[B] [[A]]".to_string())), pod.get_pin(id));
+  let other = pod.pin("[C]", 1024).unwrap();
+  let pinned = pod.heap.live;
+  assert!(pod.unpin(id));
+  assert!(!pod.unpin(id));
+  assert_eq!(Ok(None), pod.get_pin(id));
+  pod.collect().unwrap();
+  assert!(pod.heap.live < pinned);
+  assert_eq!(Ok(Some("[C]".to_string())), pod.get_pin(other));
+  let reused = pod.pin("[D]", 1024).unwrap();
+  assert_eq!(id, reused);
+}
+
+#[test]
+fn eval_opt() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  assert_eq!(pod.eval("", 1024), pod.eval("[A] e", 1024));
+  assert_eq!(Ok(None), pod.eval_opt("", 1024));
+  assert_eq!(Ok(None), pod.eval_opt(" \t", 1024));
+  assert_eq!(Ok(Some("".to_string())), pod.eval_opt("[A] e", 1024));
+  assert_eq!(Ok(Some("[A]".to_string())), pod.eval_opt("[A]", 1024));
+  assert_eq!(Err(Error::Syntax), pod.eval_opt("[", 1024));
+}
+
+#[test]
+fn put_many() {
+  let mut heap = Heap::with_capacity(1024);
+  let objects: Vec<Object> = (0..1000).map(|index| {
+    return Object::Word(format!("w{}", index).into());
+  }).collect();
+  let pointers = heap.put_many(objects).unwrap();
+  assert_eq!(1000, pointers.len());
+  assert_eq!(1000, heap.live);
+  for (index, pointer) in pointers.iter().enumerate() {
+    let expected: Rc<str> = format!("w{}", index).into();
+    assert_eq!(Ok(expected), heap.get_word(*pointer));
+  }
+  let objects = (0..25).map(|_| Object::Id).collect();
+  assert_eq!(Err(Error::Space), heap.put_many(objects));
+  assert_eq!(1000, heap.live);
+  let objects = (0..24).map(|_| Object::Id).collect();
+  assert_eq!(24, heap.put_many(objects).unwrap().len());
+  assert_eq!(Err(Error::Space), heap.new_id());
+}
+
+#[test]
+fn empty_sweep() {
+  let mut heap = Heap::with_capacity(16);
+  let stale = heap.new_word("A".into()).unwrap();
+  let generation = heap.generation();
+  assert_eq!(Ok(1), heap.sweep());
+  assert_eq!(0, heap.live);
+  assert_eq!(generation + 1, heap.generation());
+  assert_eq!(Ok(0), heap.sweep());
+  assert_eq!(generation + 2, heap.generation());
+  let fresh = heap.new_word("B".into()).unwrap();
+  assert_eq!(stale.index, fresh.index);
+  assert!(!heap.is_live(stale));
+  assert_eq!(Err(Error::Null), heap.get_ref(stale).map(|_| ()));
+  assert_eq!(Ok("B".into()), heap.get_word(fresh));
+}
+
+#[test]
+fn eval_with() {
+  let mut pod = Pod::from_string(":foo [A] [B]", 1024, 1024).unwrap();
+  let live = pod.heap.live;
+  let overlay = [("bar", "foo f"), ("foo", "bar c")];
+  let output = pod.eval_with(&overlay, "foo [C]", 1024);
+  assert_eq!(Ok("[B A] [C]".to_string()), output);
+  assert_eq!(vec!["foo".to_string()], pod.words());
+  assert_eq!(Ok("[A] [B]".to_string()), pod.eval("foo", 1024));
+  assert_eq!(live, pod.heap.live);
+  let overlay = [("baz", "[C]")];
+  let output = pod.eval_with(&overlay, ":qux baz", 1024);
+  assert_eq!(Ok(":qux [C]".to_string()), output);
+  assert_eq!(vec!["foo".to_string()], pod.words());
+  let overlay = [("Bad", "[C]")];
+  assert_eq!(Err(Error::Syntax), pod.eval_with(&overlay, "foo", 1024));
+  let overlay = [("bad", "[C")];
+  assert_eq!(Err(Error::Syntax), pod.eval_with(&overlay, "foo", 1024));
+  assert_eq!(vec!["foo".to_string()], pod.words());
+  assert_eq!(live, pod.heap.live);
+}
+
+#[test]
+fn stack_effect() {
+  let mut pod = Pod::from_string(":dup d\n:pair [A] [B]", 1024, 1024).unwrap();
+  assert_eq!(Ok(Some((1, 2))), pod.stack_effect("d"));
+  assert_eq!(Ok(Some((1, 0))), pod.stack_effect("e"));
+  assert_eq!(Ok(Some((2, 1))), pod.stack_effect("c"));
+  assert_eq!(Ok(Some((2, 2))), pod.stack_effect("f"));
+  assert_eq!(Ok(Some((3, 3))), pod.stack_effect("k"));
+  assert_eq!(Ok(Some((3, 3))), pod.stack_effect("k k k"));
+  assert_eq!(Ok(Some((0, 0))), pod.stack_effect(""));
+  assert_eq!(Ok(Some((1, 3))), pod.stack_effect("dup dup"));
+  assert_eq!(Ok(Some((0, 2))), pod.stack_effect("pair"));
+  assert_eq!(Ok(Some((1, 0))), pod.stack_effect("[e] a"));
+  assert_eq!(Ok(None), pod.stack_effect("a"));
+  assert_eq!(Ok(None), pod.stack_effect("d a"));
+  assert_eq!(Ok(None), pod.stack_effect("undefined"));
+  assert_eq!(Err(Error::Syntax), pod.stack_effect("[d"));
+}
+
+#[test]
+fn shadow() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  assert_eq!(Err(Error::Shadow), pod.eval(":a [b]", 1024));
+  assert_eq!(Err(Error::Shadow), pod.eval(":z [A]", 1024));
+  assert_eq!(Err(Error::Shadow), pod.define("e", "[A]", 1024));
+  assert_eq!(Ok(":aa [B]".to_string()), pod.eval(":aa [B]", 1024));
+  assert_eq!(vec!["aa".to_string()], pod.words());
+  pod.config_mut().opcode_runs = true;
+  assert_eq!(Err(Error::Shadow), pod.eval(":ab [B]", 1024));
+  assert_eq!(Ok(":az [B]".to_string()), pod.eval(":az [B]", 1024));
+}
+
+#[test]
+fn cache() {
+  let src = ":dup d\n:big [[A] dup c dup c dup c dup c]";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  pod.config_mut().cache_quota = 2;
+  let first = pod.eval_instrumented("big a", 1024).unwrap();
+  assert!(first.steps_used > 0);
+  assert!(!first.is_cached);
+  let summarize = |report: &EvalReport| {
+    return (report.output.clone(), report.outcome.clone(), report.steps_used);
+  };
+  let second = pod.eval_instrumented("big  a", 1024).unwrap();
+  assert!(second.is_cached);
+  assert_eq!(summarize(&first), summarize(&second));
+  let short = pod.eval_instrumented("big a", 2).unwrap();
+  assert!(!short.is_cached);
+  assert_eq!(Outcome::QuotaExhausted, short.outcome);
+  assert_eq!(2, short.steps_used);
+  assert!(short.output != first.output);
+  let exact = pod.eval_instrumented("big a", first.steps_used).unwrap();
+  assert!(exact.is_cached);
+  assert_eq!(summarize(&first), summarize(&exact));
+  let stuck = pod.eval_instrumented("a [B]", 1024).unwrap();
+  let again = pod.eval_instrumented("a\t[B]", 1024).unwrap();
+  assert!(!stuck.is_cached);
+  assert!(again.is_cached);
+  assert_eq!(summarize(&stuck), summarize(&again));
+  assert!(!pod.eval_instrumented("[B] a", 1024).unwrap().is_cached);
+  assert!(!pod.eval_instrumented("big a", 1024).unwrap().is_cached);
+  pod.eval(":big [[A]]", 1024).unwrap();
+  let changed = pod.eval_instrumented("big a", 1024).unwrap();
+  assert_eq!("[A]", changed.output);
+  assert!(!changed.is_cached);
+  pod.eval("[D] [E] c", 1024).unwrap();
+  let live = pod.heap.live;
+  pod.config_mut().cache_quota = 0;
+  pod.collect().unwrap();
+  assert!(pod.heap.live < live);
+  assert!(!pod.eval_instrumented("big a", 1024).unwrap().is_cached);
+  assert!(!pod.eval_instrumented("big a", 1024).unwrap().is_cached);
+}
+
+#[test]
+fn eval_multiline() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let src = ":foo \"a\tdoc\"\n  [A\n\t[B]   C]";
+  assert_eq!(Ok(":foo \"a doc\" [A [B] C]".to_string()),
+             pod.eval_multiline(src, 1024));
+  assert_eq!(Ok("[A [B] C]".to_string()), pod.eval("foo", 1024));
+  let src = "[A]\r\n[B]\n\tf\n";
+  assert_eq!(Ok("[B] [A]".to_string()), pod.eval_multiline(src, 1024));
+  assert_eq!(Ok("".to_string()), pod.eval_multiline("\n\t\n", 1024));
+}
+
+#[test]
+fn collapse_duplicates() {
+  let mut pod = Pod::from_string(":foo [A] [A]", 1024, 1024).unwrap();
+  let mut collapse = |src: &str| pod.collapse_duplicates(src, 1024);
+  assert_eq!(Ok("[A] d".to_string()), collapse("[A] [A]"));
+  assert_eq!(Ok("[A] d".to_string()), collapse("foo"));
+  assert_eq!(Ok("[A] d d".to_string()), collapse("[A] d d"));
+  assert_eq!(Ok("[[B] [B]] d".to_string()), collapse("[[B] [B]] [[B] [B]]"));
+  assert_eq!(Ok("[A] B [A]".to_string()), collapse("[A] B [A]"));
+  assert_eq!(Ok("[A] [B] [A]".to_string()), collapse("[A] [B] [A]"));
+  assert_eq!(Ok("".to_string()), collapse(""));
+  let src = "[A] [B] [[C] (hint)] a [D]";
+  assert_eq!(pod.eval(src, 1024), pod.collapse_duplicates(src, 1024));
+  for src in ["[A] [A]", "[[B] [B]] [[B] [B]] [C] d d"].iter() {
+    let expected = pod.eval(src, 1024).unwrap();
+    let collapsed = pod.collapse_duplicates(src, 1024).unwrap();
+    assert!(collapsed.len() < expected.len());
+    assert_eq!(Ok(expected), pod.eval(&collapsed, 1024));
+  }
+}
+
+#[test]
+fn to_source() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  for src in ["", "[A] (hint) [[B] c] d", "foo [bar [baz]] a"].iter() {
+    let root = parse(src, &mut heap, &config).unwrap();
+    let mut expected = String::new();
+    quote(root, &heap, &mut expected).unwrap();
+    assert_eq!(Ok(expected), heap.to_source(root));
+  }
+}
+
+#[test]
+fn keep_order() {
+  let src = ":zeta [A]\n:alpha [B]\n:mid [C]";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  assert_eq!(Ok(":alpha [B]\n:mid [C]\n:zeta [A]\n".to_string()),
+             pod.to_string());
+  pod.config_mut().keep_order = true;
+  assert_eq!(Ok(":zeta [A]\n:alpha [B]\n:mid [C]\n".to_string()),
+             pod.to_string());
+  pod.eval(":alpha [D]", 1024).unwrap();
+  pod.eval("~zeta", 1024).unwrap();
+  pod.eval(":zeta [E]", 1024).unwrap();
+  assert_eq!(Ok(":alpha [D]\n:mid [C]\n:zeta [E]\n".to_string()),
+             pod.to_string());
+  pod.undefine("mid").unwrap();
+  pod.eval_with(&[("beta", "[F]")], "beta", 1024).unwrap();
+  assert_eq!(Ok(":alpha [D]\n:zeta [E]\n".to_string()), pod.to_string());
+}
+
+#[test]
+fn best_effort() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let src = "[E] a [A] [B] f X [C] [D] f e";
+  let report = pod.eval_instrumented(src, 1024).unwrap();
+  assert_eq!("E [B] [A] X [D]", report.output);
+  let outcome = Outcome::Stuck {
+    first_stuck: "E".to_string(),
+  };
+  assert_eq!(outcome, report.outcome);
+  let explanations = pod.explain(src, 1024).unwrap();
+  assert_eq!(vec![
+    "word `E` is undefined".to_string(),
+    "word `X` is undefined".to_string(),
+  ], explanations);
+  let src = "[A] a [B] [C] c f [D] [E] c";
+  assert_eq!(Ok("A [B C] f [D E]".to_string()), pod.eval(src, 1024));
+  assert_eq!(2, pod.explain(src, 1024).unwrap().len());
+}
+
+#[test]
+fn inert_opcodes() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  pod.config_mut().strict_arity = true;
+  assert_eq!(Ok("[A] [B] g".to_string()), pod.eval("[A] [B] g", 1024));
+  assert_eq!(Ok("[A] h".to_string()), pod.eval("[A] h", 1024));
+  assert_eq!(Ok("g h".to_string()), pod.eval("g h", 1024));
+  assert_eq!(Ok("[A] h [B]".to_string()), pod.eval("[A] h [C] [B] f e", 1024));
+}
+
+#[test]
+fn is_live() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  let kept = parse("[A] [B]", &mut heap, &config).unwrap();
+  let lost = parse("[C]", &mut heap, &config).unwrap();
+  let generation = heap.generation();
+  assert!(heap.is_live(kept));
+  assert!(heap.is_live(lost));
+  heap.mark(kept).unwrap();
+  heap.sweep().unwrap();
+  assert!(heap.generation() != generation);
+  assert!(heap.is_live(kept));
+  assert!(!heap.is_live(lost));
+  assert_eq!(Err(Error::Null), heap.get_ref(lost).map(|_| ()));
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  let generation = pod.generation();
+  assert_eq!(generation, pod.generation());
+  pod.eval("foo", 1024).unwrap();
+  assert!(pod.generation() != generation);
+}
+
+#[test]
+fn eval_split() {
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  let split = ("[A]".to_string(), "a".to_string());
+  assert_eq!(Ok(split), pod.eval_split("a foo", 1024));
+  let split = ("[C] [B]".to_string(), "X [D] f".to_string());
+  assert_eq!(Ok(split), pod.eval_split("X [D] f [B] [C] f", 1024));
+  assert_eq!(pod.eval("X [D] f [B] [C] f", 1024),
+             Ok("X [D] f [C] [B]".to_string()));
+  let split = ("[A]".to_string(), "".to_string());
+  assert_eq!(Ok(split), pod.eval_split("foo", 1024));
+  let split = ("[A] foo".to_string(), "b".to_string());
+  assert_eq!(Ok(split), pod.eval_split("b foo foo", 2));
+}
+
+#[test]
+fn uppercase_words() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  for name in ["A", "B", "Z"].iter() {
+    let root = parse(name, &mut heap, &config).unwrap();
+    let element = heap.get_elements(root).unwrap()[0];
+    assert_eq!(Ok(name.to_string()), heap.get_word(element).map(|x| {
+      return x.to_string();
+    }));
+  }
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  pod.config_mut().opcode_runs = true;
+  assert_eq!(Ok("[B] [A]".to_string()), pod.eval("[A] [B] f", 1024));
+  assert_eq!(Ok("A".to_string()), pod.eval("[A] a", 1024));
+  assert_eq!(Err(Error::Syntax), pod.define("A", "[B]", 1024));
+  assert!(pod.words().is_empty());
+  let classes = pod.classify("A");
+  assert_eq!(TokenClass::UndefinedWord, classes[0].1);
+}
+
+#[test]
+fn checkpoint() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  heap.set_monotonic(true);
+  let kept = parse("[A] [B]", &mut heap, &config).unwrap();
+  let live = heap.live;
+  let get_occupied = |heap: &Heap| -> Vec<bool> {
+    return heap.nodes.iter().map(|x| x.is_some()).collect();
+  };
+  let occupied = get_occupied(&heap);
+  let checkpoint = heap.checkpoint();
+  let lost = parse("[C] [[D]]", &mut heap, &config).unwrap();
+  assert!(heap.live > live);
+  assert_eq!(Ok(heap.live - live), heap.rollback(checkpoint));
+  assert_eq!(live, heap.live);
+  assert_eq!(occupied, get_occupied(&heap));
+  assert!(heap.is_live(kept));
+  assert!(!heap.is_live(lost));
+  let again = parse("[C] [[D]]", &mut heap, &config).unwrap();
+  assert_eq!(lost.index, again.index);
+  assert!(heap.is_live(again));
+  assert!(!heap.is_live(lost));
+  assert_eq!(Err(Error::Null), heap.get_ref(lost).map(|_| ()));
+  heap.set_monotonic(false);
+  assert_eq!(Err(Error::Assert), heap.rollback(checkpoint));
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  pod.set_monotonic(true);
+  let frontier = pod.heap.frontier;
+  assert_eq!(Err(Error::Syntax), pod.eval("[A] [B", 1024));
+  assert_eq!(Err(Error::Syntax), pod.eval("[A] [B", 1024));
+  assert_eq!(frontier, pod.heap.frontier);
+  assert_eq!(Ok("[A]".to_string()), pod.eval("foo", 1024));
+}
+
+#[test]
+fn eval_program() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  assert_eq!(Ok(":foo [A]\n[A]".to_string()),
+             pod.eval_program(":foo [A]; foo", 1024));
+  let src = ":bar [B;C];bar;\n[D] [E] f ;; foo";
+  assert_eq!(Ok(":bar [B;C]\n[B;C]\n[E] [D]\n[A]".to_string()),
+             pod.eval_program(src, 1024));
+  assert_eq!(Ok(":baz \"one; two\" [F]".to_string()),
+             pod.eval_program(":baz \"one; two\" [F];", 1024));
+  assert_eq!(Some("one; two".to_string()), pod.doc("baz"));
+  assert_eq!(Ok("".to_string()), pod.eval_program(" ; ;", 1024));
+  assert_eq!(Err(Error::Syntax), pod.eval_program("[A; ]]; foo", 1024));
+}
+
+#[test]
+fn quote_depth() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  let root = parse("[[[[[A]]]]] B", &mut heap, &config).unwrap();
+  assert_eq!(Ok("[[…]] B".to_string()), heap.quote_depth(root, 2));
+  assert_eq!(Ok("… B".to_string()), heap.quote_depth(root, 0));
+  assert_eq!(heap.to_source(root), heap.quote_depth(root, 5));
+  let root = parse("[A [B] [C [D]] e] (hint) f", &mut heap, &config).unwrap();
+  assert_eq!(Ok("[A … … e] (hint) f".to_string()), heap.quote_depth(root, 1));
+  assert_eq!(Ok("[A [B] [C …] e] (hint) f".to_string()),
+             heap.quote_depth(root, 2));
+  let mut pod = Pod::from_string(":foo [[[C]]] b", 1024, 1024).unwrap();
+  assert_eq!(Ok("[…] [B]".to_string()), pod.eval_elided("foo [B]", 1024, 1));
+  assert_eq!(Ok("[[[[C]]]]".to_string()), pod.eval_elided("foo", 1024, 4));
+  assert_eq!(Err(Error::Syntax), pod.eval_elided("[", 1024, 1));
+}
+
+#[test]
+fn validate_like_parse() {
+  let mut heap = Heap::with_capacity(1024);
+  let mut config = Config { nesting_quota: 4, ..Config::default() };
+  let sources = [
+    "", "[A] [B] f", "[[A] (hint) foo] a", "[A", "A]", "] [", "[A]]",
+    "z", "[A] z", "ab", "[[[[A]]]]", "[[[[[A]]]]]", "Z z",
+  ];
+  for src in sources.iter() {
+    let expected = parse(src, &mut heap, &config).map(|_| ());
+    assert_eq!(expected, validate(src, &config), "{}", src);
+  }
+  config.opcode_runs = true;
+  assert_eq!(Ok(()), validate("ab [cd]", &config));
+  assert_eq!(Err(Error::Syntax), validate("[z]", &config));
+}
+
+#[test]
+fn cost_model() {
+  let src = "[A] [B] c [C] c [D] c [E] c [F] c";
+  let mut uniform = Pod::from_string("", 1024, 1024).unwrap();
+  let mut costly = Pod::from_string("", 1024, 1024).unwrap();
+  costly.config_mut().costs = CostModel::uniform().with('c', 10);
+  let cheap = uniform.eval_instrumented(src, 1024).unwrap();
+  let dear = costly.eval_instrumented(src, 1024).unwrap();
+  assert_eq!(cheap.output, dear.output);
+  assert_eq!(Outcome::Completed, dear.outcome);
+  assert_eq!(11, cheap.steps_used);
+  assert_eq!(56, dear.steps_used);
+  let quota = cheap.steps_used;
+  let report = uniform.eval_instrumented(src, quota).unwrap();
+  assert_eq!(Outcome::Completed, report.outcome);
+  let report = costly.eval_instrumented(src, quota).unwrap();
+  assert_eq!(Outcome::QuotaExhausted, report.outcome);
+  assert_eq!(Ok("[A B] [C] c [D] c [E] c [F] c".to_string()),
+             costly.eval(src, quota));
+  assert_eq!(1, CostModel::uniform().with('e', 0).get('e'));
+  assert_eq!(1, CostModel::default().get('Z'));
+}
+
+#[test]
+fn live_nodes() {
+  let mut heap = Heap::with_capacity(1024);
+  let config = Config::default();
+  let root = parse("[A]", &mut heap, &config).unwrap();
+  heap.push_root(root);
+  let nodes: Vec<(Gc, String)> = heap.live_nodes().collect();
+  assert_eq!(heap.live, nodes.len());
+  assert!(nodes.iter().any(|(x, _)| *x == root));
+  assert!(nodes.iter().any(|(_, x)| x == "block `[A]`"));
+  assert!(nodes.iter().any(|(_, x)| x == "word `A`"));
+  heap.pop_root().unwrap();
+  assert_eq!(nodes, heap.live_nodes().collect::<Vec<_>>());
+  heap.mark_roots().unwrap();
+  heap.sweep().unwrap();
+  assert_eq!(0, heap.live_nodes().count());
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  let is_garbage = |(_, x): &(usize, String)| x == "word `Q`";
+  assert_eq!(pod.heap.live, pod.live_nodes().len());
+  pod.eval_no_gc("[Q] e", 1024).unwrap();
+  assert!(pod.live_nodes().iter().any(is_garbage));
+  pod.collect().unwrap();
+  assert!(!pod.live_nodes().iter().any(is_garbage));
+  assert!(pod.live_nodes().iter().any(|(_, x)| x == "word `A`"));
+}
+
+#[test]
+fn default_prelude() {
+  let mut pod = Pod::from_home(None, 1 << 12, 1024).unwrap();
+  assert_eq!(Ok("[B] [A]".to_string()), pod.eval("[A] [B] swap", 1024));
+  assert_eq!(Ok("[B] [C] [A]".to_string()), pod.eval("[A] [B] [C] rot", 1024));
+  let home = std::env::temp_dir().join("sundial-default-prelude");
+  std::fs::create_dir_all(home.join("pod")).unwrap();
+  std::fs::write(home.join("pod").join("default.md"), ":foo [X]").unwrap();
+  let mut pod = Pod::from_home(Some(&home), 1 << 12, 1024);
+  let missing = Pod::from_home(Some(&home.join("missing")), 1 << 12, 1024);
+  std::fs::remove_dir_all(&home).unwrap();
+  let pod = pod.as_mut().unwrap();
+  assert_eq!(Ok("[X]".to_string()), pod.eval("foo", 1024));
+  assert_eq!(vec!["foo".to_string()], pod.words());
+  assert_eq!(Some(Error::Home), missing.err());
+}
+
+#[test]
+fn thread_depths() {
+  let mut heap = Heap::with_capacity(1024);
+  let tab = HashMap::new();
+  let config = Config::default();
+  let root = parse("[A] [B] X [C] f e", &mut heap, &config).unwrap();
+  let mut thread = Thread::with_continuation(root, &config);
+  assert_eq!((0, 1, 0), (thread.env_len(), thread.con_len(), thread.err_len()));
+  thread.step(&mut heap, &tab).unwrap();
+  thread.step(&mut heap, &tab).unwrap();
+  assert_eq!(2, thread.env_len());
+  assert_eq!(0, thread.err_len());
+  assert!(thread.con_len() > 0);
+  thread.step(&mut heap, &tab).unwrap();
+  assert_eq!((0, 3), (thread.env_len(), thread.err_len()));
+  thread.step(&mut heap, &tab).unwrap();
+  assert_eq!((1, 3), (thread.env_len(), thread.err_len()));
+  thread.step(&mut heap, &tab).unwrap();
+  assert_eq!((0, 5), (thread.env_len(), thread.err_len()));
+  thread.step(&mut heap, &tab).unwrap();
+  assert_eq!((0, 0, 6), (thread.env_len(), thread.con_len(), thread.err_len()));
+}
+
+#[test]
+fn call_by_need() {
+  let src = "[[B] [C] c [D] c [E] c [F] c] d a e a";
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let by_name = pod.eval_instrumented(src, 1024).unwrap();
+  pod.config_mut().call_by_need = Some(1024);
+  let by_need = pod.eval_instrumented(src, 1024).unwrap();
+  assert_eq!("[B C D E F]", by_need.output);
+  assert_eq!(by_name.output, by_need.output);
+  // The body takes nine steps. By need, they're taken once, and each
+  // application then pushes the block they produced.
+  assert_eq!(23, by_name.steps_used);
+  assert_eq!(16, by_need.steps_used);
+  let sources = [
+    "[d] [A] f d a a", "[A] [e X] a", "[[B] a] d a a", "", "[X] [[Y] c] a",
+  ];
+  for strict_arity in [false, true].iter() {
+    pod.config_mut().strict_arity = *strict_arity;
+    for src in sources.iter() {
+      pod.config_mut().call_by_need = None;
+      let expected = pod.eval(src, 1024);
+      pod.config_mut().call_by_need = Some(1024);
+      assert_eq!(expected, pod.eval(src, 1024), "{}", src);
+    }
+  }
+  pod.config_mut().strict_arity = false;
+  pod.config_mut().call_by_need = Some(2);
+  assert_eq!(Ok("[B C D E F]".to_string()), pod.eval(src, 1024));
+}
+
+#[test]
+fn audit() {
+  let src = ":foo [A] [B]\n:bar foo f [C] c";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  pod.eval("bar foo d a", 1024).unwrap();
+  pod.pin("[D] b", 1024).unwrap();
+  let roots: Vec<Gc> = pod.tab.values().copied().collect();
+  let report = pod.heap.audit(&roots).unwrap();
+  assert_eq!(0, report.leaked);
+  assert_eq!(report.live, report.reachable);
+  assert!(report.live > 0);
+  let config = pod.config;
+  let live = pod.heap.live;
+  parse("[E] [F]", &mut pod.heap, &config).unwrap();
+  let report = pod.heap.audit(&roots).unwrap();
+  assert_eq!(pod.heap.live - live, report.leaked);
+  assert!(report.leaked > 0);
+  pod.collect().unwrap();
+  assert_eq!(0, pod.heap.audit(&roots).unwrap().leaked);
+}
+
+#[test]
+fn reduced_nesting() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  pod.config_mut().nesting_quota = 4;
+  assert_eq!(Ok("[[[[A]]]]".to_string()), pod.eval("[A] b b b", 1024));
+  assert_eq!(Err(Error::Space), pod.eval("[A] b b b b", 1024));
+  let src = "[A] [B] [C] j j";
+  assert_eq!(Ok("[[A] [[B] [C]]]".to_string()), pod.eval(src, 1024));
+  assert_eq!(Err(Error::Space), pod.eval("[[[[A]]]] [B] j", 1024));
+  assert_eq!(Ok("[[A] B]".to_string()), pod.eval("[[A]] [B] c", 1024));
+  pod.config_mut().nesting_quota = 64;
+  assert_eq!(Err(Error::Space), pod.eval("[A] [f b f d a] d a", 1 << 20));
+}
+
+#[test]
+fn trace_snapshots() {
+  let mut pod = Pod::from_string(":sqr [A] f", 1024, 1024).unwrap();
+  let expected = vec!["[A] a", "A", "A"];
+  assert_eq!(expected, pod.trace("[A] a", 1024).unwrap());
+  let trace = pod.trace("[B] sqr a", 1024).unwrap();
+  assert_eq!(Some(&"[A] B".to_string()), trace.last());
+  assert_eq!(2, pod.trace("[B] sqr a", 2).unwrap().len());
+}
+
+#[test]
+fn bug_is_an_error() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let code = parse("[A] [B]", &mut pod.heap, &pod.config).unwrap();
+  let mut thread = Thread::with_continuation(code, &pod.config);
+  let result = thread.dispatch(code, &mut pod.heap, &pod.tab);
+  assert_eq!(Err(Error::Bug), result);
+}
+
+#[test]
+#[should_panic(expected = "thread can't run `[A] [B]`")]
+fn bug_panics_when_debugging() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  pod.config_mut().debug_panic_on_bug = true;
+  let code = parse("[A] [B]", &mut pod.heap, &pod.config).unwrap();
+  let mut thread = Thread::with_continuation(code, &pod.config);
+  let _ = thread.dispatch(code, &mut pod.heap, &pod.tab);
+}
+
+#[test]
+fn parse_in_pieces() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let config = pod.config;
+  let whole = parse("[A B] foo", &mut pod.heap, &config).unwrap();
+  let mut state = ParseState::new();
+  for piece in ["[A", " B]", " f", "o", "o"].iter() {
+    state.feed(piece, &mut pod.heap, &config).unwrap();
+  }
+  let pieces = state.finish(&mut pod.heap, &config).unwrap();
+  assert_eq!(pod.heap.to_source(whole), pod.heap.to_source(pieces));
+  let mut state = ParseState::new();
+  state.feed("[A", &mut pod.heap, &config).unwrap();
+  assert_eq!(Err(Error::Syntax), state.finish(&mut pod.heap, &config));
+}
+
+#[test]
+fn simplify() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let cases = [
+    ("b a", ""),
+    ("d f", "d"),
+    ("d f f e", ""),
+    ("Foo [d e] [B] e f f", "Foo []"),
+    ("[A] [b a] a", "[A]"),
+    ("[Foo] [Bar] [d f b a] a", "[Foo] [Bar] [d] a"),
+  ];
+  for (src, expected) in cases.iter() {
+    let mut dst = String::new();
+    let root = parse(src, &mut pod.heap, &pod.config).unwrap();
+    let root = pod.heap.simplify(root).unwrap();
+    quote(root, &pod.heap, &mut dst).unwrap();
+    assert_eq!(expected, &dst, "{}", src);
+  }
+  assert_eq!(Ok("Foo d".to_string()), pod.simplify("Foo [b a d f] a", 1024));
+}
+
+#[test]
+fn usage_counts() {
+  let src = ":foo [A]\n:bar [foo] [foo]\n:baz [[foo] B]";
+  let pod = Pod::from_string(src, 1024, 1024).unwrap();
+  let counts = pod.usage_counts().unwrap();
+  assert_eq!(Some(&3), counts.get("foo"));
+  assert_eq!(Some(&1), counts.get("A"));
+  assert_eq!(Some(&1), counts.get("B"));
+  assert_eq!(None, counts.get("bar"));
+  assert_eq!(3, counts.len());
+}
+
+#[test]
+fn cat_empty_block() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let config = pod.config;
+  for src in ["[] [A] c", "[A] [] c"].iter() {
+    let root = parse(src, &mut pod.heap, &config).unwrap();
+    let elements = pod.heap.get_elements(root).unwrap();
+    let block = if src.starts_with("[]") { elements[1] } else { elements[0] };
+    let reduction = reduce(root, &mut pod.heap, &pod.tab, &config, 1024)
+      .unwrap();
+    assert_eq!(Ok("[A]".to_string()), pod.heap.to_source(reduction.term));
+    let elements = pod.heap.get_elements(reduction.term).unwrap();
+    assert_eq!(vec![block], elements);
+  }
+  assert_eq!(Ok("[]".to_string()), pod.eval("[] [] c", 1024));
+  assert_eq!(Ok("[A B]".to_string()), pod.eval("[A] [B] c", 1024));
+}
+
+#[test]
+fn build() {
+  let src = "\
+:foo [A]
+:bar [B
+:baz foo
+  foo
+:a [C]
+:quux baz bar
+[A] [B] c";
+  let (mut pod, errors) = Pod::build(src, 1024, 1024);
+  assert_eq!(vec![(2, Error::Syntax), (5, Error::Shadow)], errors);
+  assert_eq!(Ok("[A] [A] bar".to_string()), pod.eval("quux", 1024));
+  assert_eq!(vec!["baz", "foo", "quux"], pod.words());
+  let (_, errors) = Pod::build(":foo [A]\n:bar foo", 1024, 1024);
+  assert!(errors.is_empty());
+}
+
+#[test]
+fn line_numbers() {
+  let src = ":foo [A]\n:bar foo\n:baz [B\n:quux bar\n:zot [C]";
+  let error = (3, Error::Syntax);
+  assert_eq!(Some(error), Pod::from_string(src, 1024, 1024).err());
+  let src = ":foo [A]\n:bar [B]\n  foo\n:a [C]";
+  let error = (4, Error::Shadow);
+  assert_eq!(Some(error), Pod::from_string(src, 1024, 1024).err());
+}
+
+#[test]
+fn quote_styles() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let config = pod.config;
+  let compact = QuoteStyle {
+    space_in_sequence: false,
+    space_in_block: false,
+  };
+  let spaced = QuoteStyle {
+    space_in_sequence: true,
+    space_in_block: true,
+  };
+  let cases = [
+    ("[A] [B] c", "[A][B]c", "[ A ] [ B ] c"),
+    ("Foo [[] (x)] a b", "Foo[[](x)]a b", "Foo [ [] (x) ] a b"),
+    ("[[A B] [C]] d", "[[A B][C]]d", "[ [ A B ] [ C ] ] d"),
+    ("", "", ""),
+  ];
+  for (src, compact_src, spaced_src) in cases.iter() {
+    let root = parse(src, &mut pod.heap, &config).unwrap();
+    let styles = [
+      (QuoteStyle::default(), src),
+      (compact, compact_src),
+      (spaced, spaced_src),
+    ];
+    for (style, expected) in styles.iter() {
+      let mut dst = String::new();
+      quote_styled(root, &pod.heap, style, &mut dst).unwrap();
+      assert_eq!(*expected, &dst);
+      let copy = parse(&dst, &mut pod.heap, &config).unwrap();
+      assert_eq!(pod.heap.to_source(root), pod.heap.to_source(copy));
+    }
+  }
+  let dst = pod.eval_styled("[A] [B] f", 1024, &compact);
+  assert_eq!(Ok("[B][A]".to_string()), dst);
+}
+
+#[test]
+fn may_underflow() {
+  let mut pod = Pod::from_string(":pair [A] [B]", 1024, 1024).unwrap();
+  let config = pod.config;
+  let block = parse("[e]", &mut pod.heap, &config).unwrap();
+  let block = pod.heap.get_elements(block).unwrap()[0];
+  assert_eq!(Ok(true), pod.heap.may_underflow(block, 0, &pod.tab));
+  assert_eq!(Ok(false), pod.heap.may_underflow(block, 1, &pod.tab));
+  assert_eq!(Ok(false), pod.may_underflow("pair c e", 0));
+  assert_eq!(Ok(true), pod.may_underflow("pair e e e", 0));
+  assert_eq!(Ok(false), pod.may_underflow("pair e e e", 1));
+  assert_eq!(Ok(true), pod.may_underflow("[A] f a", 8));
+  assert_eq!(Ok(false), pod.may_underflow("[[A] d] a e", 0));
+}
+
+#[test]
+fn split_library() {
+  let src = "\
+:foo [A]
+:bar [foo] [B]
+:baz \"joins\" bar c
+:zot [quux] bar
+:quux zot e";
+  let pod = Pod::from_string(src, 1024, 1024).unwrap();
+  let dir = std::env::temp_dir().join("sundial-split-library");
+  let _ = std::fs::remove_dir_all(&dir);
+  pod.export_split(&dir).unwrap();
+  std::fs::write(dir.join("notes.txt"), ":foo [C]").unwrap();
+  let baz = std::fs::read_to_string(dir.join("baz.md")).unwrap();
+  let copy = Pod::import_split(&dir, 1024, 1024);
+  std::fs::remove_dir_all(&dir).unwrap();
+  assert_eq!(":baz \"joins\" [foo B]\n", baz);
+  let copy = copy.unwrap();
+  assert_eq!(pod.to_string(), copy.to_string());
+  assert_eq!(Some("joins".to_string()), copy.doc("baz"));
+  let missing = std::env::temp_dir().join("sundial-split-missing");
+  assert_eq!(Some(Error::Io), Pod::import_split(&missing, 64, 64).err());
+}
+
+#[test]
+fn isolate() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let mut check = |src: &str, applied: &str, isolated: &str| {
+    let applied_src = format!("[X] [Y] {} a", src);
+    let isolated_src = format!("[X] [Y] {} l", src);
+    assert_eq!(Ok(applied.to_string()), pod.eval(&applied_src, 1024));
+    assert_eq!(Ok(isolated.to_string()), pod.eval(&isolated_src, 1024));
+  };
+  check("[[A] [B]]", "[X] [Y] [A] [B]", "[X] [Y] [A] [B]");
+  check("[f]", "[Y] [X]", "[X] [Y] [f] l");
+  check("[e [A]]", "[X] [A]", "[X] [Y] [e [A]] l");
+  check("[[A] d c]", "[X] [Y] [A A]", "[X] [Y] [A A]");
+  check("[[[A] b] l a]", "[X] [Y] [A]", "[X] [Y] [A]");
+  let src = "[Z] [[A] d d] l [B]";
+  assert_eq!(Ok("[Z] [[A] [A] d] l [B]".to_string()), pod.eval(src, 5));
+  assert_eq!(Ok("[Z] [A] [A] [A] [B]".to_string()), pod.eval(src, 6));
+  assert_eq!(Ok(Some((0, 2))), pod.stack_effect("[[A] d] l"));
+  assert_eq!(Ok(Some((2, 3))), pod.stack_effect("f [[A]] l"));
+  assert_eq!(Ok(None), pod.stack_effect("[f] l"));
+  pod.config_mut().strategy = Strategy::Innermost;
+  let src = "[Z] [[A] [B] f] l";
+  assert_eq!(Ok("[Z] [B] [A]".to_string()), pod.eval(src, 1024));
+  assert_eq!(Ok("[Z]".to_string()), pod.eval("[Z] [[[A] e] l] l", 1024));
+}
+
+#[test]
+fn clone_term() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let config = pod.config;
+  let reachable = |heap: &Heap, root: Gc| {
+    let mut nodes = std::collections::HashSet::new();
+    let mut stack = vec![root];
+    while let Some(pointer) = stack.pop() {
+      if !nodes.insert(pointer) {
+        continue;
+      }
+      match heap.get_ref(pointer).unwrap() {
+        &Object::Block(body) => {
+          stack.push(body);
+        }
+        &Object::Sequence(fst, snd) => {
+          stack.push(fst);
+          stack.push(snd);
+        }
+        _ => {
+          continue;
+        }
+      }
+    }
+    return nodes;
+  };
+  let root = parse("[A] d [[B] (x)] c", &mut pod.heap, &config).unwrap();
+  let block = pod.heap.new_block(root).unwrap();
+  let shared = pod.heap.new_sequence(block, block).unwrap();
+  let live = pod.heap.live;
+  let copy = pod.heap.clone_term(shared).unwrap();
+  let originals = reachable(&pod.heap, shared);
+  let copies = reachable(&pod.heap, copy);
+  assert_eq!(pod.heap.to_source(shared), pod.heap.to_source(copy));
+  assert_eq!(originals.len(), copies.len());
+  assert_eq!(live + copies.len(), pod.heap.live);
+  assert!(originals.is_disjoint(&copies));
+  let mut pod = Pod::from_string(":foo [A] [[B] c]", 1024, 1024).unwrap();
+  assert_eq!(Ok(true), pod.copy_word("bar", "foo"));
+  assert_eq!(pod.eval("foo", 1024), pod.eval("bar", 1024));
+  let foo = reachable(&pod.heap, pod.tab["foo"]);
+  let bar = reachable(&pod.heap, pod.tab["bar"]);
+  assert!(foo.is_disjoint(&bar));
+  assert_eq!(Ok(false), pod.copy_word("baz", "quux"));
+  assert_eq!(Err(Error::Shadow), pod.copy_word("f", "foo"));
+  assert_eq!(vec!["bar", "foo"], pod.words());
+  pod.undefine("foo").unwrap();
+  assert_eq!(Ok("[A] [[B] c]".to_string()), pod.eval("bar", 1024));
+}
+
+#[test]
+fn eval_state_json() {
+  let mut pod = Pod::from_string("", 1024, 1024).unwrap();
+  let frame = |con: &str, env: &str, err: &str| {
+    return format!("{{\"con\":[{}],\"env\":[{}],\"err\":[{}]}}", con, env, err);
+  };
+  let state = |steps, frame: String, frames: &str| {
+    return format!(
+      "{{\"steps\":{},\"frame\":{},\"frames\":[{}]}}", steps, frame, frames);
+  };
+  let expected = state(0, frame("\"[A] [B] f\"", "", ""), "");
+  assert_eq!(Ok(expected), pod.eval_state_json("[A] [B] f", 0));
+  let expected = state(2, frame("\"f\"", "\"[A]\",\"[B]\"", ""), "");
+  assert_eq!(Ok(expected), pod.eval_state_json("[A] [B] f", 2));
+  let expected = state(3, frame("", "\"[B]\",\"[A]\"", ""), "");
+  assert_eq!(Ok(expected), pod.eval_state_json("[A] [B] f", 3));
+  let expected = state(2, frame("", "", "\"Foo\",\"a\""), "");
+  assert_eq!(Ok(expected), pod.eval_state_json("Foo a", 8));
+  let expected = state(4, frame("\"d\"", "\"[B]\"", ""),
+    &frame("\"[C]\"", "\"[Z]\"", ""));
+  assert_eq!(Ok(expected), pod.eval_state_json("[Z] [[B] d] l [C]", 4));
+  let mut target = String::new();
+  push_json_string("\"a\\b\"\n", &mut target);
+  assert_eq!("\"\\\"a\\\\b\\\"\\u000a\"", target);
+}
+
+#[test]
+fn normal_definitions() {
+  let mut pod = Pod::from_string(":foo [A]", 1024, 1024).unwrap();
+  let mut insert = |src| {
+    let report = pod.eval_instrumented(src, 1024).unwrap();
+    return (report.output, report.outcome, report.steps_used);
+  };
+  let (output, outcome, steps) = insert(":bar [A] [B]");
+  assert_eq!((":bar [A] [B]", 0), (&*output, steps));
+  assert_eq!(Outcome::Completed, outcome);
+  let (output, outcome, steps) = insert(":bar [A] a");
+  assert_eq!((":bar A", 3), (&*output, steps));
+  assert_eq!(Outcome::Stuck { first_stuck: "A".to_string() }, outcome);
+  let (output, outcome, steps) = insert(":baz [B] Quux c (x) [C] e e");
+  assert_eq!(":baz [B] Quux c e", output);
+  assert_eq!(Outcome::Stuck { first_stuck: "Quux".to_string() }, outcome);
+  assert!(steps > 0);
+  let (output, outcome, steps) = insert(":baz [B] Quux [C] c e");
+  assert_eq!(":baz [B] Quux [C] c e", output);
+  assert_eq!(Outcome::Stuck { first_stuck: "Quux".to_string() }, outcome);
+  assert_eq!(0, steps);
+  assert_eq!(":baz [B] [A]", insert(":baz [B] foo").0);
+  let config = pod.config;
+  for (src, is_normal) in [
+    ("[A] [B]", true), ("[A] a", false), ("a [A]", true), ("foo", false),
+    ("Bar [A] e", false), ("Bar [A] c", true), ("[[A] a]", true),
+    ("[A] (x)", false), ("[(x)]", true),
+  ].iter() {
+    let root = parse(src, &mut pod.heap, &config).unwrap();
+    assert_eq!(Ok(*is_normal), pod.heap.is_normal(root, &pod.tab, &config));
+  }
+}
+
+#[test]
+fn expansion_quota() {
+  let src = ":go [A] go\n:dup2 d d\n:pop2 e e";
+  let mut pod = Pod::from_string(src, 1 << 12, 1024).unwrap();
+  pod.config_mut().expansion_quota = Some(64);
+  assert_eq!(Err(Error::Expansion), pod.eval("go", 1 << 20));
+  let src = "[A] dup2 dup2 pop2 pop2 [B] d d d d e e e e";
+  let report = pod.eval_instrumented(src, 1 << 20).unwrap();
+  assert_eq!("[A] [B]", report.output);
+  assert!(report.steps_used > 16);
+  pod.config_mut().expansion_quota = Some(4);
+  assert_eq!(Ok("[A] [B]".to_string()), pod.eval(src, 1 << 20));
+  pod.config_mut().expansion_quota = Some(3);
+  assert_eq!(Err(Error::Expansion), pod.eval(src, 1 << 20));
+  let src = "[A] dup2 pop2 [dup2 pop2] a";
+  pod.config_mut().call_by_need = Some(1024);
+  assert_eq!(Err(Error::Expansion), pod.eval(src, 1 << 20));
+  pod.config_mut().expansion_quota = Some(4);
+  assert_eq!(Ok("[A]".to_string()), pod.eval(src, 1 << 20));
+  pod.config_mut().call_by_need = None;
+  pod.config_mut().expansion_quota = None;
+  let report = pod.eval_instrumented("go", 64).unwrap();
+  assert_eq!(Outcome::QuotaExhausted, report.outcome);
+}
+
+#[test]
+fn call() {
+  let src = ":flip f\n:under [A] f\n:nothing [] e";
+  let mut pod = Pod::from_string(src, 1024, 1024).unwrap();
+  assert_eq!(Ok("[B] [A]".to_string()), pod.call("flip", &["A", "B"], 1024));
+  let args = ["[X] d", "Y Z"];
+  assert_eq!(Ok("[Y Z] [[X] d]".to_string()), pod.call("flip", &args, 1024));
+  assert_eq!(Ok("[A] [B]".to_string()), pod.call("under", &["B"], 1024));
+  assert_eq!(Err(Error::Shadow), pod.call("f", &["A", "B"], 1024));
+  assert_eq!(Ok("[A] [B] cab".to_string()), pod.call("cab", &["A", "B"], 1024));
+  pod.config_mut().opcode_runs = true;
+  assert_eq!(Err(Error::Shadow), pod.call("cab", &["A", "B"], 1024));
+  assert_eq!(Ok("[A] flop".to_string()), pod.call("flop", &["A"], 1024));
+  assert_eq!(Ok("".to_string()), pod.call("nothing", &[], 1024));
+  assert_eq!(Err(Error::Syntax), pod.call("flip", &["[A", "B]"], 1024));
+  assert_eq!(Err(Error::Syntax), pod.call("flip f", &["A", "B"], 1024));
 }